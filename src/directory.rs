@@ -0,0 +1,205 @@
+use clap::ValueEnum;
+use lofty::probe::Probe;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use minau::player::metadata::MetaData;
+use minau::track::Track;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum SortOrder {
+    /// Plain path order
+    Path,
+    /// Path order, comparing runs of digits by their value ("2" before "10")
+    #[default]
+    Natural,
+    /// Disc and track number tags within each directory
+    Track,
+}
+
+/// Recursively collects every audio file below `dir`.
+/// Files are recognised by their content, not their extension. Hidden entries are
+/// skipped unless `hidden` is set, and symlinked directories are followed only once.
+pub fn expand<P: AsRef<Path>>(dir: P, order: SortOrder, hidden: bool) -> Vec<Track> {
+    let mut visited = HashSet::new();
+    let mut files = Vec::new();
+    walk(dir.as_ref(), hidden, &mut visited, &mut files);
+
+    match order {
+        SortOrder::Path => files.sort(),
+        SortOrder::Natural => {
+            files.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()))
+        }
+        SortOrder::Track => {
+            let mut keyed: Vec<_> = files
+                .into_iter()
+                .map(|path| (disc_and_track(&path), path))
+                .collect();
+            keyed.sort_by(|(a_num, a), (b_num, b)| {
+                natural_cmp(&parent_of(a), &parent_of(b))
+                    .then(a_num.cmp(b_num))
+                    .then_with(|| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()))
+            });
+            files = keyed.into_iter().map(|(_, path)| path).collect();
+        }
+    }
+
+    files
+        .into_iter()
+        .map(|path| Track::new(path.to_string_lossy(), None))
+        .collect()
+}
+
+fn walk(dir: &Path, hidden: bool, visited: &mut HashSet<PathBuf>, files: &mut Vec<PathBuf>) {
+    // Canonical paths catch symlinks that point back up the tree
+    let Ok(canonical) = dir.canonicalize() else {
+        return;
+    };
+    if !visited.insert(canonical) {
+        return;
+    }
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        if !hidden && entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let path = entry.path();
+        if path.is_dir() {
+            walk(&path, hidden, visited, files);
+        } else if is_audio(&path) {
+            files.push(path);
+        }
+    }
+}
+
+fn is_audio(path: &Path) -> bool {
+    infer::get_from_path(path)
+        .ok()
+        .flatten()
+        .is_some_and(|kind| kind.matcher_type() == infer::MatcherType::Audio)
+}
+
+/// Tracks without tags sort after the numbered ones of their directory
+fn disc_and_track(path: &Path) -> (u32, u32) {
+    let Some(metadata) = Probe::open(path)
+        .ok()
+        .and_then(|probe| MetaData::new(probe).ok())
+    else {
+        return (u32::MAX, u32::MAX);
+    };
+
+    (
+        metadata.disc().unwrap_or(1),
+        metadata.track_number().unwrap_or(u32::MAX),
+    )
+}
+
+fn parent_of(path: &Path) -> String {
+    path.parent()
+        .map(|dir| dir.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Compares strings case-insensitively, treating each run of digits as one number
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        let ordering = match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                x.len().cmp(&y.len()).then(x.cmp(&y))
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                a.next();
+                b.next();
+                ordering
+            }
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Consumes a run of digits, dropping leading zeros
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        if !(digits.is_empty() && c == '0') {
+            digits.push(c);
+        }
+    }
+    digits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_compare_by_value() {
+        assert_eq!(natural_cmp("2", "10"), Ordering::Less);
+        assert_eq!(
+            natural_cmp("track 10.flac", "track 9.flac"),
+            Ordering::Greater
+        );
+        assert_eq!(natural_cmp("disc 2", "disc 2"), Ordering::Equal);
+    }
+
+    #[test]
+    fn leading_zeros_do_not_count() {
+        assert_eq!(natural_cmp("01 intro.mp3", "1 intro.mp3"), Ordering::Equal);
+        assert_eq!(natural_cmp("007", "10"), Ordering::Less);
+        assert_eq!(natural_cmp("0", "00"), Ordering::Equal);
+        assert_eq!(natural_cmp("0", "1"), Ordering::Less);
+    }
+
+    #[test]
+    fn text_and_digits_mixed() {
+        assert_eq!(natural_cmp("cd1 track9", "cd1 track10"), Ordering::Less);
+        assert_eq!(natural_cmp("cd2 track1", "cd10 track1"), Ordering::Less);
+        assert_eq!(natural_cmp("b1", "a2"), Ordering::Greater);
+        assert_eq!(natural_cmp("Track 3", "track 3"), Ordering::Equal);
+        assert_eq!(natural_cmp("track", "track 1"), Ordering::Less);
+    }
+
+    #[test]
+    fn equal_numbers_fall_back_to_what_follows() {
+        assert_eq!(natural_cmp("5a", "5b"), Ordering::Less);
+        assert_eq!(natural_cmp("05 - Outro", "5 - Intro"), Ordering::Greater);
+        assert_eq!(natural_cmp("5", "5.1"), Ordering::Less);
+    }
+
+    #[test]
+    fn numbers_past_u64_still_compare() {
+        let max = u64::MAX.to_string();
+        let past = "18446744073709551616";
+        assert_eq!(natural_cmp(&max, past), Ordering::Less);
+        assert_eq!(
+            natural_cmp(
+                "123456789012345678901234567890",
+                "123456789012345678901234567891"
+            ),
+            Ordering::Less
+        );
+        assert_eq!(
+            natural_cmp("099999999999999999999", "99999999999999999999"),
+            Ordering::Equal
+        );
+    }
+}
@@ -1,18 +1,20 @@
-use crate::{display_info::string_info, err, player::metadata::MetaData};
+use crate::display_info::string_info;
 use image::GenericImageView;
+use minau::{error::Result, player::metadata::MetaData};
 use minifb::{Window, WindowOptions};
 use std::{
-    process::exit,
     sync::{Arc, Mutex},
     thread,
     time::Duration,
 };
 
-pub fn display(data: Vec<u8>, filename: &str, metadata: MetaData, close: Arc<Mutex<bool>>) {
-    let img = image::load_from_memory(&data).unwrap_or_else(|e| {
-        err!("Unsupported image type\n{}", e);
-        exit(1);
-    });
+pub fn display(
+    data: Vec<u8>,
+    filename: &str,
+    metadata: MetaData,
+    close: Arc<Mutex<bool>>,
+) -> Result<()> {
+    let img = image::load_from_memory(&data)?;
 
     let (width, height) = img.dimensions();
     let (mut last_width, mut last_height) = (width as usize, height as usize);
@@ -25,8 +27,7 @@ pub fn display(data: Vec<u8>, filename: &str, metadata: MetaData, close: Arc<Mut
             resize: true,
             ..WindowOptions::default()
         },
-    )
-    .unwrap();
+    )?;
 
     let mut buffer: Vec<u32> = img
         .to_rgb8()
@@ -34,13 +35,9 @@ pub fn display(data: Vec<u8>, filename: &str, metadata: MetaData, close: Arc<Mut
         .map(|px| u32::from_be_bytes([0, px[0], px[1], px[2]]))
         .collect();
 
-    window
-        .update_with_buffer(&buffer, last_width, last_height)
-        .unwrap();
+    window.update_with_buffer(&buffer, last_width, last_height)?;
     thread::sleep(Duration::from_millis(100));
-    window
-        .update_with_buffer(&buffer, last_width, last_height)
-        .unwrap();
+    window.update_with_buffer(&buffer, last_width, last_height)?;
 
     while window.is_open()
         && !window.is_key_down(minifb::Key::Escape)
@@ -68,11 +65,11 @@ pub fn display(data: Vec<u8>, filename: &str, metadata: MetaData, close: Arc<Mut
 
             last_width = width;
             last_height = height;
-            window
-                .update_with_buffer(&buffer, last_width, last_height)
-                .unwrap();
+            window.update_with_buffer(&buffer, last_width, last_height)?;
         } else {
             window.update();
         }
     }
+
+    Ok(())
 }
@@ -0,0 +1,143 @@
+use minau::player::eq::{Band, EqControl};
+use std::fs;
+use std::path::Path;
+
+pub struct Preset {
+    pub name: String,
+    pub bands: Vec<Band>,
+}
+
+/// The equalizer settings that the `e` key cycles through
+pub struct EqPresets {
+    control: EqControl,
+    presets: Vec<Preset>,
+    current: usize,
+}
+
+impl EqPresets {
+    /// Offers a flat response, the bands given with `--eq` if any, and every preset from the file.
+    /// Starts on `initial`, or on the `--eq` bands when there are some.
+    pub fn new(
+        bands: Vec<Band>,
+        from_file: Vec<Preset>,
+        initial: Option<&str>,
+    ) -> Result<Self, String> {
+        let current_default = usize::from(!bands.is_empty());
+        let mut presets = vec![Preset {
+            name: String::from("flat"),
+            bands: Vec::new(),
+        }];
+        if !bands.is_empty() {
+            presets.push(Preset {
+                name: String::from("custom"),
+                bands,
+            });
+        }
+        presets.extend(from_file);
+
+        let current = match initial {
+            Some(name) => presets
+                .iter()
+                .position(|preset| preset.name == name)
+                .ok_or_else(|| format!("No EQ preset named {}", name))?,
+            None => current_default,
+        };
+
+        Ok(EqPresets {
+            control: EqControl::new(presets[current].bands.clone()),
+            presets,
+            current,
+        })
+    }
+
+    pub fn control(&self) -> &EqControl {
+        &self.control
+    }
+
+    /// Switches every playing and future track to the next preset and returns its name
+    pub fn next(&mut self) -> &str {
+        self.current = (self.current + 1) % self.presets.len();
+        let preset = &self.presets[self.current];
+        self.control.set_bands(preset.bands.clone());
+        &preset.name
+    }
+}
+
+/// Reads a preset file: a `[name]` line starts each preset, followed by one band per line
+/// in the same form `--eq` takes. Blank lines and lines starting with `#` are skipped.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Preset>, String> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse(&content, path)
+}
+
+/// Parses the text of the preset file at `path`, which errors point to
+fn parse(content: &str, path: &Path) -> Result<Vec<Preset>, String> {
+    let mut presets: Vec<Preset> = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            presets.push(Preset {
+                name: name.trim().to_string(),
+                bands: Vec::new(),
+            });
+            continue;
+        }
+
+        let error = |e: String| format!("{}:{}: {}", path.display(), number + 1, e);
+        let preset = presets
+            .last_mut()
+            .ok_or_else(|| error(String::from("band outside of a [preset]")))?;
+        preset.bands.push(line.parse().map_err(error)?);
+    }
+
+    Ok(presets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Result<Vec<Preset>, String> {
+        super::parse(content, Path::new("eq.txt"))
+    }
+
+    #[test]
+    fn sections_comments_and_blank_lines() {
+        let presets = parse(
+            "# Presets for the e key
+
+[Bass boost]
+low:105:6
+
+# Tames the highs a little
+[ vocal ]
+  2700:3:2
+high:8000:-2
+[empty]
+",
+        )
+        .unwrap();
+        let names: Vec<_> = presets.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["Bass boost", "vocal", "empty"]);
+        assert_eq!(presets[0].bands, ["low:105:6".parse::<Band>().unwrap()]);
+        assert_eq!(presets[1].bands.len(), 2);
+        assert!(presets[2].bands.is_empty());
+    }
+
+    #[test]
+    fn band_outside_a_section() {
+        let error = parse("# no section yet\n100:3\n[late]\n").err().unwrap();
+        assert!(error.starts_with("eq.txt:2: "), "{}", error);
+    }
+
+    #[test]
+    fn bad_band_names_its_line() {
+        let error = parse("[one]\n100:3\n\n100:loud\n").err().unwrap();
+        assert!(error.starts_with("eq.txt:4: "), "{}", error);
+    }
+}
@@ -0,0 +1,123 @@
+use std::{fmt, io};
+
+/// Everything that can go wrong while opening or playing a single entry
+#[derive(Debug)]
+pub enum Error {
+    /// The file could not be opened or read
+    Io(io::Error),
+    /// The audio data could not be decoded
+    Decode(rodio::decoder::DecoderError),
+    /// Tags or audio properties could not be read
+    Metadata(lofty::error::LoftyError),
+    /// The audio output could not be opened
+    Output(rodio::StreamError),
+    /// The audio host could not list its output devices
+    Devices(rodio::DevicesError),
+    /// No output device has the given name or index
+    NoSuchDevice(String),
+    /// The WAV file rendered to could not be written
+    Wav(hound::Error),
+    /// The embedded album art could not be decoded
+    Image(image::ImageError),
+    /// The album art window could not be shown
+    Window(minifb::Error),
+    /// The terminal could not be switched into raw mode or read from
+    Terminal(io::Error),
+    /// A remote stream could not be fetched or probed
+    Stream(Box<dyn std::error::Error + Send + Sync>),
+    /// An XSPF playlist is not well-formed XML
+    Xspf(xml::reader::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Decode(e) => write!(f, "Failed to build decoder: {}", e),
+            Error::Metadata(e) => write!(f, "Failed to read metadata: {}", e),
+            Error::Output(e) => write!(f, "Failed to open stream: {}", e),
+            Error::Devices(e) => write!(f, "Failed to list output devices: {}", e),
+            Error::NoSuchDevice(name) => write!(f, "No output device named or numbered {}", name),
+            Error::Wav(e) => write!(f, "Failed to write WAV file: {}", e),
+            Error::Image(e) => write!(f, "Unsupported image type: {}", e),
+            Error::Window(e) => write!(f, "Failed to open window: {}", e),
+            Error::Terminal(e) => write!(f, "Terminal error: {}", e),
+            Error::Stream(e) => write!(f, "Failed to setup url player: {}", e),
+            Error::Xspf(e) => write!(f, "Failed to read XSPF playlist: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) | Error::Terminal(e) => Some(e),
+            Error::Decode(e) => Some(e),
+            Error::Metadata(e) => Some(e),
+            Error::Output(e) => Some(e),
+            Error::Devices(e) => Some(e),
+            Error::NoSuchDevice(_) => None,
+            Error::Wav(e) => Some(e),
+            Error::Image(e) => Some(e),
+            Error::Window(e) => Some(e),
+            Error::Stream(e) => Some(e.as_ref()),
+            Error::Xspf(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<rodio::decoder::DecoderError> for Error {
+    fn from(e: rodio::decoder::DecoderError) -> Self {
+        Error::Decode(e)
+    }
+}
+
+impl From<lofty::error::LoftyError> for Error {
+    fn from(e: lofty::error::LoftyError) -> Self {
+        Error::Metadata(e)
+    }
+}
+
+impl From<rodio::StreamError> for Error {
+    fn from(e: rodio::StreamError) -> Self {
+        Error::Output(e)
+    }
+}
+
+impl From<rodio::DevicesError> for Error {
+    fn from(e: rodio::DevicesError) -> Self {
+        Error::Devices(e)
+    }
+}
+
+impl From<hound::Error> for Error {
+    fn from(e: hound::Error) -> Self {
+        Error::Wav(e)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self {
+        Error::Image(e)
+    }
+}
+
+impl From<minifb::Error> for Error {
+    fn from(e: minifb::Error) -> Self {
+        Error::Window(e)
+    }
+}
+
+impl From<xml::reader::Error> for Error {
+    fn from(e: xml::reader::Error) -> Self {
+        Error::Xspf(e)
+    }
+}
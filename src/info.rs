@@ -1,11 +1,11 @@
 use crate::display_info;
-use crate::player::metadata::MetaData;
 use crossterm::terminal;
 use crossterm::{
     cursor::{self, MoveToPreviousLine},
     execute,
     terminal::{Clear, ClearType},
 };
+use minau::player::metadata::MetaData;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio::time::{Duration, Instant, sleep};
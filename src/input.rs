@@ -1,8 +1,8 @@
 use crate::{
     err,
     info::{info, info_with_restore, info_with_restore_url},
-    play_url::UrlPlayer,
-    player::{metadata::MetaData, play::MusicPlay},
+    play_music::Settings,
+    queue::{Command, Queue, Repeat, Shuffle},
 };
 use crossterm::{
     cursor::{Hide, Show},
@@ -11,85 +11,269 @@ use crossterm::{
     style::Stylize,
     terminal::{disable_raw_mode, enable_raw_mode},
 };
+use minau::{
+    error::{Error, Result},
+    player::{
+        metadata::MetaData,
+        play::{MusicPlay, when_silent},
+        stereo::StereoControl,
+        stretch::SpeedControl,
+        url::UrlPlayer,
+    },
+};
 use std::{
     io::stdout,
-    process::exit,
     sync::{Arc, Mutex},
     time::Duration,
 };
 
-pub fn init_terminal() {
-    enable_raw_mode().unwrap_or_else(|e| {
-        err!("Failed to initialize terminal: {}", e);
-        exit(1);
-    });
-    execute!(stdout(), Hide).unwrap_or_else(|e| {
-        err!("Failed to initialize terminal: {}", e);
-        exit(1);
-    });
+pub fn init_terminal() -> Result<()> {
+    enable_raw_mode().map_err(Error::Terminal)?;
+    execute!(stdout(), Hide).map_err(Error::Terminal)?;
+    Ok(())
 }
 
 pub fn deinit() {
-    disable_raw_mode().unwrap_or_else(|e| {
+    if let Err(e) = disable_raw_mode() {
         err!("Failed to disable raw mode: {}", e);
         err!("Please execute 'reset' command");
-        exit(1);
-    });
+    }
 
-    execute!(stdout(), Show).unwrap_or_else(|e| {
-        err!("Failed to initialize terminal: {}", e);
-        exit(1);
-    });
+    if let Err(e) = execute!(stdout(), Show) {
+        err!("Failed to restore cursor: {}", e);
+    }
 }
 
 const VOLUME_STEP: f32 = 0.05;
 const POLL_INTERVAL_MS: u64 = 100;
 const SEEK_STEP_SECS: u64 = 5;
+const SPEED_STEP: f32 = 0.1;
+const BALANCE_STEP: f32 = 0.1;
+
+/// Parses a typed track number into a zero-based queue index
+fn jump_target(input: &str, queue_len: usize) -> Option<usize> {
+    input
+        .parse::<usize>()
+        .ok()
+        .filter(|n| (1..=queue_len).contains(n))
+        .map(|n| n - 1)
+}
+
+fn shuffle_message(shuffle: Option<Shuffle>) -> String {
+    let mode = match shuffle {
+        None => "off",
+        Some(Shuffle::Track) => "tracks",
+        Some(Shuffle::Album) => "albums",
+    };
+    format!("Shuffle: {}", mode.cyan())
+}
+
+fn repeat_message(repeat: Repeat) -> String {
+    let mode = match repeat {
+        Repeat::None => "off",
+        Repeat::One => "one",
+        Repeat::All => "all",
+    };
+    format!("Repeat: {}", mode.cyan())
+}
+
+fn eq_message(preset: &str) -> String {
+    format!("EQ: {}", preset.cyan())
+}
+
+/// Moves the balance on `,` and `.`, toggles mono on `m` and the channel swap on `x`
+fn adjust_stereo(stereo: &StereoControl, key: char) -> String {
+    let on_off = |on: bool| if on { "on" } else { "off" };
+    match key {
+        ',' | '.' => {
+            let step = if key == ',' {
+                -BALANCE_STEP
+            } else {
+                BALANCE_STEP
+            };
+            // Rounded so repeated steps land back on the center exactly
+            let balance = stereo.set_balance(((stereo.balance() + step) * 10.0).round() / 10.0);
+            let side = match balance {
+                b if b < 0.0 => format!("L {}", (-b * 100.0).round()),
+                b if b > 0.0 => format!("R {}", (b * 100.0).round()),
+                _ => String::from("center"),
+            };
+            format!("Balance: {}", side.cyan())
+        }
+        'm' => format!("Mono: {}", on_off(stereo.toggle_mono()).cyan()),
+        _ => format!("Swap L/R: {}", on_off(stereo.toggle_swap()).cyan()),
+    }
+}
+
+fn sleep_message(sleep: &str) -> String {
+    format!("Sleep: {}", sleep.cyan())
+}
+
+/// Steps the speed, or resets it to normal when `step` is zero, and describes the result
+fn adjust_speed(speed: &SpeedControl, step: f32) -> String {
+    let target = if step == 0.0 {
+        1.0
+    } else {
+        // Rounded so repeated steps land on tenths instead of drifting
+        ((speed.get() + step) * 10.0).round() / 10.0
+    };
+    format!("Speed: {}", format!("{:.1}x", speed.set(target)).cyan())
+}
+
+/// Seeks a stream by [`SEEK_STEP_SECS`], as far as the server lets it be fetched in ranges.
+/// The player stays free for the output loop while the server answers.
+async fn seek_url(play: &Mutex<UrlPlayer>, forward: bool) -> String {
+    let (seeker, cur_pos) = {
+        let play = play.lock().unwrap();
+        let seeker = play.seeker().filter(|_| play.is_seekable());
+        (seeker, play.get_pos())
+    };
+    let Some(seeker) = seeker else {
+        return "Seek is not supported in stream mode".red().to_string();
+    };
+
+    let step = Duration::from_secs(SEEK_STEP_SECS);
+    let (direction, new_pos) = if forward {
+        ("forward", cur_pos + step)
+    } else {
+        ("backward", cur_pos.saturating_sub(step))
+    };
+    let result = tokio::task::spawn_blocking(move || seeker.seek(new_pos))
+        .await
+        .unwrap();
+    match result {
+        Ok(()) => format!(
+            "Seeked {} ({} -> {})",
+            direction,
+            humantime::format_duration(Duration::from_secs(cur_pos.as_secs())),
+            humantime::format_duration(Duration::from_secs(new_pos.as_secs()))
+        ),
+        Err(e) => format!("Seek failed: {}", e).red().to_string(),
+    }
+}
+
+/// Sets the loop start on `a`, closes the loop on `b` and clears it on `c`
+fn adjust_loop(play: &mut MusicPlay, key: char) -> String {
+    let whole_secs =
+        |pos: Duration| humantime::format_duration(Duration::from_secs(pos.as_secs())).to_string();
+    match key {
+        'a' => format!("Loop start: {}", whole_secs(play.set_loop_start()).cyan()),
+        'b' => match play.set_loop_end() {
+            Some(Ok((start, end))) => format!(
+                "Looping: {}",
+                format!("{} - {}", whole_secs(start), whole_secs(end)).cyan()
+            ),
+            Some(Err(e)) => format!("Seek failed, cannot loop: {:?}", e)
+                .red()
+                .to_string(),
+            None => "Set the loop start with a first, then b after it"
+                .red()
+                .to_string(),
+        },
+        _ => {
+            play.clear_loop();
+            String::from("Loop cleared")
+        }
+    }
+}
 
 pub async fn get_input_url_mode(
     url_player: Arc<Mutex<UrlPlayer>>,
     url: String,
     key_state: Arc<Mutex<bool>>,
-) {
+    queue: Arc<Mutex<Queue>>,
+    settings: Settings,
+) -> Result<Command> {
     let url = url.as_str();
-    init_terminal();
+    let mut jump = String::new();
+    init_terminal()?;
     loop {
         if *key_state.lock().unwrap() {
-            break;
+            return Ok(Command::Next);
         }
 
-        if !poll(Duration::from_millis(POLL_INTERVAL_MS)).unwrap() {
+        if !poll(Duration::from_millis(POLL_INTERVAL_MS)).map_err(Error::Terminal)? {
             continue;
         }
 
-        let event = read().unwrap_or_else(|e| {
-            err!("Failed to read key: {}", e);
-            exit(1);
-        });
+        let event = read().map_err(Error::Terminal)?;
 
         if let Event::Key(key) = event {
             if key.kind != KeyEventKind::Press {
                 continue;
             }
 
+            // Messages give way to whatever the station says is playing by then
+            let stream_title = url_player.lock().unwrap().stream_title();
+            let url = stream_title.as_deref().unwrap_or(url);
+
             match key.code {
                 KeyCode::Char('q') => {
                     info("Exitting...");
-                    deinit();
-                    println!();
-                    exit(0);
+                    return Ok(Command::Quit);
                 }
                 KeyCode::Char('>') | KeyCode::Right => {
                     info("Next track");
-                    return;
+                    return Ok(Command::Next);
+                }
+                KeyCode::Char('<') | KeyCode::Left => {
+                    info("Previous track");
+                    return Ok(Command::Previous);
+                }
+                KeyCode::Char('r') => {
+                    info("Restart track");
+                    return Ok(Command::Restart);
+                }
+                KeyCode::Char(c @ '0'..='9') => {
+                    jump.push(c);
+                    info_with_restore_url(format!("Jump to track: {}", jump.as_str().cyan()), url);
+                }
+                KeyCode::Enter if !jump.is_empty() => {
+                    if let Some(index) = jump_target(&jump, queue.lock().unwrap().len()) {
+                        info(format!("Jump to track {}", index + 1));
+                        return Ok(Command::Jump(index));
+                    }
+                    info_with_restore_url(format!("No track {}", jump).red().to_string(), url);
+                    jump.clear();
+                }
+                KeyCode::Esc if !jump.is_empty() => {
+                    jump.clear();
+                    info_with_restore_url("Jump cancelled", url);
+                }
+                KeyCode::Char('s') => {
+                    let shuffle = queue.lock().unwrap().toggle_shuffle();
+                    info_with_restore_url(shuffle_message(shuffle), url);
+                }
+                KeyCode::Char('R') => {
+                    let repeat = queue.lock().unwrap().cycle_repeat();
+                    info_with_restore_url(repeat_message(repeat), url);
+                }
+                KeyCode::Char('e') => {
+                    let message = eq_message(settings.eq.lock().unwrap().next());
+                    info_with_restore_url(message, url);
+                }
+                KeyCode::Char(c @ ('[' | ']' | '\\')) => {
+                    let step = match c {
+                        '[' => -SPEED_STEP,
+                        ']' => SPEED_STEP,
+                        _ => 0.0,
+                    };
+                    info_with_restore_url(adjust_speed(&settings.speed, step), url);
+                }
+                KeyCode::Char('z') => {
+                    let message = sleep_message(&settings.sleep_timer.lock().unwrap().cycle());
+                    info_with_restore_url(message, url);
+                }
+                KeyCode::Char(c @ (',' | '.' | 'm' | 'x')) => {
+                    info_with_restore_url(adjust_stereo(&settings.stereo, c), url);
                 }
                 KeyCode::Char(' ') => {
-                    let play = url_player.lock().unwrap();
-                    let msg = if play.is_paused() {
-                        play.resume();
+                    let paused = url_player.lock().unwrap().is_paused();
+                    let msg = if paused {
+                        url_player.lock().unwrap().resume();
                         "|> Resumed"
                     } else {
-                        play.pause();
+                        when_silent(&url_player, |player| player.pause()).await;
                         "|| Paused"
                     };
                     info_with_restore_url(msg, url);
@@ -100,17 +284,9 @@ pub async fn get_input_url_mode(
                 KeyCode::Char('-') | KeyCode::Char('_') | KeyCode::Char('j') => {
                     adjust_volume_url(Arc::clone(&url_player), -VOLUME_STEP, url);
                 }
-                KeyCode::Char('l') => {
-                    info_with_restore_url(
-                        "Seek is not supported in stream mode".red().to_string(),
-                        url,
-                    );
-                }
-                KeyCode::Char('h') => {
-                    info_with_restore_url(
-                        "Seek is not supported in stream mode".red().to_string(),
-                        url,
-                    );
+                KeyCode::Char(c @ ('h' | 'l')) => {
+                    let message = seek_url(&url_player, c == 'l').await;
+                    info_with_restore_url(message, url);
                 }
                 KeyCode::Char(c) => {
                     info_with_restore_url(format!("Unknown key: {}", c.red()), url);
@@ -127,22 +303,22 @@ pub async fn get_input(
     filename: String,
     path: String,
     metadata: MetaData,
-) {
+    queue: Arc<Mutex<Queue>>,
+    settings: Settings,
+) -> Result<Command> {
     let path = path.as_str();
-    init_terminal();
+    let mut jump = String::new();
+    init_terminal()?;
     loop {
         if *quit.lock().unwrap() {
-            return;
+            return Ok(Command::Next);
         }
 
-        if !poll(Duration::from_millis(POLL_INTERVAL_MS)).unwrap() {
+        if !poll(Duration::from_millis(POLL_INTERVAL_MS)).map_err(Error::Terminal)? {
             continue;
         }
 
-        let event = read().unwrap_or_else(|e| {
-            err!("Failed to read key: {}", e);
-            exit(1);
-        });
+        let event = read().map_err(Error::Terminal)?;
 
         if let Event::Key(key) = event {
             if key.kind != KeyEventKind::Press {
@@ -152,21 +328,124 @@ pub async fn get_input(
             match key.code {
                 KeyCode::Char('q') => {
                     info("Exitting...");
-                    deinit();
-                    println!();
-                    exit(0);
+                    return Ok(Command::Quit);
                 }
                 KeyCode::Char('>') | KeyCode::Right => {
                     info("Next track");
-                    return;
+                    return Ok(Command::Next);
+                }
+                KeyCode::Char('<') | KeyCode::Left => {
+                    info("Previous track");
+                    return Ok(Command::Previous);
+                }
+                KeyCode::Char('r') => {
+                    info("Restart track");
+                    return Ok(Command::Restart);
+                }
+                KeyCode::Char(c @ '0'..='9') => {
+                    jump.push(c);
+                    info_with_restore(
+                        format!("Jump to track: {}", jump.as_str().cyan()),
+                        filename.clone(),
+                        path.to_string(),
+                        metadata.clone(),
+                    );
+                }
+                KeyCode::Enter if !jump.is_empty() => {
+                    if let Some(index) = jump_target(&jump, queue.lock().unwrap().len()) {
+                        info(format!("Jump to track {}", index + 1));
+                        return Ok(Command::Jump(index));
+                    }
+                    info_with_restore(
+                        format!("No track {}", jump).red().to_string(),
+                        filename.clone(),
+                        path.to_string(),
+                        metadata.clone(),
+                    );
+                    jump.clear();
+                }
+                KeyCode::Esc if !jump.is_empty() => {
+                    jump.clear();
+                    info_with_restore(
+                        "Jump cancelled",
+                        filename.clone(),
+                        path.to_string(),
+                        metadata.clone(),
+                    );
+                }
+                KeyCode::Char('s') => {
+                    let shuffle = queue.lock().unwrap().toggle_shuffle();
+                    info_with_restore(
+                        shuffle_message(shuffle),
+                        filename.clone(),
+                        path.to_string(),
+                        metadata.clone(),
+                    );
+                }
+                KeyCode::Char('R') => {
+                    let repeat = queue.lock().unwrap().cycle_repeat();
+                    info_with_restore(
+                        repeat_message(repeat),
+                        filename.clone(),
+                        path.to_string(),
+                        metadata.clone(),
+                    );
+                }
+                KeyCode::Char('e') => {
+                    let message = eq_message(settings.eq.lock().unwrap().next());
+                    info_with_restore(
+                        message,
+                        filename.clone(),
+                        path.to_string(),
+                        metadata.clone(),
+                    );
+                }
+                KeyCode::Char(c @ ('[' | ']' | '\\')) => {
+                    let step = match c {
+                        '[' => -SPEED_STEP,
+                        ']' => SPEED_STEP,
+                        _ => 0.0,
+                    };
+                    info_with_restore(
+                        adjust_speed(&settings.speed, step),
+                        filename.clone(),
+                        path.to_string(),
+                        metadata.clone(),
+                    );
+                }
+                KeyCode::Char('z') => {
+                    let message = sleep_message(&settings.sleep_timer.lock().unwrap().cycle());
+                    info_with_restore(
+                        message,
+                        filename.clone(),
+                        path.to_string(),
+                        metadata.clone(),
+                    );
+                }
+                KeyCode::Char(c @ (',' | '.' | 'm' | 'x')) => {
+                    info_with_restore(
+                        adjust_stereo(&settings.stereo, c),
+                        filename.clone(),
+                        path.to_string(),
+                        metadata.clone(),
+                    );
+                }
+                KeyCode::Char(c @ ('a' | 'b' | 'c')) => {
+                    let message = adjust_loop(&mut music_play.lock().unwrap(), c);
+                    info_with_restore(
+                        message,
+                        filename.clone(),
+                        path.to_string(),
+                        metadata.clone(),
+                    );
                 }
                 KeyCode::Char(' ') => {
-                    let mut play = music_play.lock().unwrap();
-                    let msg = if play.is_paused() {
-                        play.resume();
+                    let paused = music_play.lock().unwrap().is_paused();
+                    let msg = if paused {
+                        music_play.lock().unwrap().resume();
                         "|> Resumed"
                     } else {
-                        play.pause();
+                        when_silent(&music_play, MusicPlay::pause).await;
                         "|| Paused"
                     };
                     info_with_restore(msg, filename.clone(), path.to_string(), metadata.clone());
@@ -0,0 +1,37 @@
+//! The playback core of minau.
+//!
+//! Nothing in this crate writes to the terminal or exits the process; every failure is
+//! returned as an [`Error`]. The `minau` binary is a terminal front end built on top of it.
+//!
+//! ```no_run
+//! use minau::{Output, Player};
+//!
+//! # fn main() -> minau::Result<()> {
+//! let output = Output::open_default()?;
+//! let player = Player::new("song.flac")?;
+//! println!("{:?}", player.metadata()?.title());
+//!
+//! let mut music = player.play(output.mixer());
+//! std::thread::sleep(std::time::Duration::from_secs(5));
+//! music.pause();
+//! # Ok(())
+//! # }
+//! ```
+
+mod biquad;
+pub mod error;
+pub mod loudness;
+pub mod m3u;
+pub mod player;
+pub mod pls;
+pub mod track;
+pub mod xspf;
+
+pub use error::{Error, Result};
+pub use player::metadata::MetaData;
+pub use player::output::{Output, OutputConfig};
+pub use player::play::MusicPlay;
+pub use player::player_structs::Player;
+pub use player::replaygain::{GainMode, ReplayGain};
+pub use player::url::{UrlPlayer, setup_url_player};
+pub use track::Track;
@@ -0,0 +1,442 @@
+use crate::biquad::Biquad;
+use crate::error::Result;
+use crate::player::player_structs::Player;
+use lofty::config::WriteOptions;
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::probe::Probe;
+use lofty::tag::{ItemKey, Tag};
+use rodio::Source;
+use std::f64::consts::PI;
+use std::path::Path;
+
+/// Loudness that ReplayGain 2.0 brings every track or album to, in LUFS
+pub const REFERENCE_LUFS: f64 = -18.0;
+
+/// Gating blocks are built from sub-blocks of this many milliseconds
+const SUB_BLOCK_MS: u32 = 100;
+/// Sub-blocks in one 400ms momentary block
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+/// Sub-blocks in one 3s short-term block
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const INTEGRATED_RELATIVE_GATE_LU: f64 = -10.0;
+const RANGE_RELATIVE_GATE_LU: f64 = -20.0;
+/// The true peak is searched for at this multiple of the sample rate
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+/// The result of measuring a track or an album
+#[derive(Clone, Copy, Debug)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS, or `None` for silence
+    pub integrated: Option<f64>,
+    /// Loudness range in LU
+    pub range: f64,
+    /// Highest inter-sample peak as a linear amplitude
+    pub true_peak: f64,
+}
+
+impl Loudness {
+    /// ReplayGain 2.0 adjustment in dB
+    pub fn gain(&self) -> Option<f64> {
+        self.integrated.map(|lufs| REFERENCE_LUFS - lufs)
+    }
+
+    /// True peak in dBTP, or `None` for digital silence
+    pub fn true_peak_db(&self) -> Option<f64> {
+        (self.true_peak > 0.0).then(|| 20.0 * self.true_peak.log10())
+    }
+}
+
+/// Measures loudness as specified by ITU-R BS.1770 and EBU R128/Tech 3342
+pub struct Meter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<KWeighting>,
+    upsamplers: Vec<Upsampler>,
+    sub_block_len: usize,
+    sub_block_pos: usize,
+    sub_block_sum: f64,
+    /// Mean weighted energy of every complete 100ms sub-block
+    sub_blocks: Vec<f64>,
+    true_peak: f64,
+}
+
+impl Meter {
+    pub fn new(channels: u16, sample_rate: u32) -> Self {
+        let channels = channels.max(1) as usize;
+        Meter {
+            channels,
+            weights: (0..channels).map(|c| channel_weight(c, channels)).collect(),
+            filters: (0..channels)
+                .map(|_| KWeighting::new(sample_rate as f64))
+                .collect(),
+            upsamplers: (0..channels).map(|_| Upsampler::new()).collect(),
+            sub_block_len: (sample_rate * SUB_BLOCK_MS / 1000).max(1) as usize,
+            sub_block_pos: 0,
+            sub_block_sum: 0.0,
+            sub_blocks: Vec::new(),
+            true_peak: 0.0,
+        }
+    }
+
+    /// Feeds interleaved samples; a trailing partial frame is ignored
+    pub fn add_samples(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (c, &sample) in frame.iter().enumerate() {
+                let sample = sample as f64;
+                self.true_peak = self.true_peak.max(self.upsamplers[c].peak(sample));
+
+                let filtered = self.filters[c].process(sample);
+                self.sub_block_sum += self.weights[c] * filtered * filtered;
+            }
+
+            self.sub_block_pos += 1;
+            if self.sub_block_pos == self.sub_block_len {
+                self.sub_blocks
+                    .push(self.sub_block_sum / self.sub_block_len as f64);
+                self.sub_block_pos = 0;
+                self.sub_block_sum = 0.0;
+            }
+        }
+    }
+
+    pub fn loudness(&self) -> Loudness {
+        Self::combined(std::slice::from_ref(self))
+    }
+
+    /// Loudness of several tracks measured as one programme, as for an album.
+    /// Gating blocks never span two tracks.
+    pub fn combined(meters: &[Meter]) -> Loudness {
+        let momentary: Vec<f64> = meters
+            .iter()
+            .flat_map(|m| blocks(&m.sub_blocks, MOMENTARY_SUB_BLOCKS))
+            .collect();
+        let short_term: Vec<f64> = meters
+            .iter()
+            .flat_map(|m| blocks(&m.sub_blocks, SHORT_TERM_SUB_BLOCKS))
+            .collect();
+
+        Loudness {
+            integrated: integrated(&momentary),
+            range: range(&short_term),
+            true_peak: meters.iter().map(|m| m.true_peak).fold(0.0, f64::max),
+        }
+    }
+}
+
+/// Decodes a whole file and measures it
+pub fn scan<P: AsRef<Path>>(path: P) -> Result<Meter> {
+    let decoder = Player::new(path)?.decoder;
+    let mut meter = Meter::new(decoder.channels(), decoder.sample_rate());
+
+    let mut buffer = Vec::with_capacity(4096);
+    for sample in decoder {
+        buffer.push(sample);
+        if buffer.len() == buffer.capacity() {
+            meter.add_samples(&buffer);
+            buffer.clear();
+        }
+    }
+    meter.add_samples(&buffer);
+
+    Ok(meter)
+}
+
+/// Stores the results as ReplayGain 2.0 tags, replacing any that were there
+pub fn write_replay_gain<P: AsRef<Path>>(
+    path: P,
+    track: &Loudness,
+    album: Option<&Loudness>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let mut file = Probe::open(path)?.read()?;
+
+    let tag_type = file.primary_tag_type();
+    if file.primary_tag().is_none() {
+        file.insert_tag(Tag::new(tag_type));
+    }
+    let Some(tag) = file.primary_tag_mut() else {
+        return Ok(());
+    };
+
+    set_gain(
+        tag,
+        track,
+        ItemKey::ReplayGainTrackGain,
+        ItemKey::ReplayGainTrackPeak,
+    );
+    match album {
+        Some(album) => set_gain(
+            tag,
+            album,
+            ItemKey::ReplayGainAlbumGain,
+            ItemKey::ReplayGainAlbumPeak,
+        ),
+        None => {
+            tag.remove_key(&ItemKey::ReplayGainAlbumGain);
+            tag.remove_key(&ItemKey::ReplayGainAlbumPeak);
+        }
+    }
+
+    file.save_to_path(path, WriteOptions::default())?;
+    Ok(())
+}
+
+fn set_gain(tag: &mut Tag, loudness: &Loudness, gain_key: ItemKey, peak_key: ItemKey) {
+    match loudness.gain() {
+        Some(gain) => {
+            tag.insert_text(gain_key, format!("{:.2} dB", gain));
+            tag.insert_text(peak_key, format!("{:.6}", loudness.true_peak));
+        }
+        None => {
+            tag.remove_key(&gain_key);
+            tag.remove_key(&peak_key);
+        }
+    }
+}
+
+/// Mean energy of every window of `len` sub-blocks, moving one sub-block at a time
+fn blocks(sub_blocks: &[f64], len: usize) -> impl Iterator<Item = f64> + '_ {
+    sub_blocks
+        .windows(len)
+        .map(move |window| window.iter().sum::<f64>() / len as f64)
+}
+
+fn lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+fn integrated(blocks: &[f64]) -> Option<f64> {
+    let audible = || {
+        blocks
+            .iter()
+            .copied()
+            .filter(|&e| lufs(e) > ABSOLUTE_GATE_LUFS)
+    };
+    let threshold = lufs(mean(audible())?) + INTEGRATED_RELATIVE_GATE_LU;
+
+    mean(audible().filter(|&e| lufs(e) > threshold)).map(lufs)
+}
+
+fn range(blocks: &[f64]) -> f64 {
+    let audible = || {
+        blocks
+            .iter()
+            .copied()
+            .filter(|&e| lufs(e) > ABSOLUTE_GATE_LUFS)
+    };
+    let Some(audible_mean) = mean(audible()) else {
+        return 0.0;
+    };
+    let threshold = lufs(audible_mean) + RANGE_RELATIVE_GATE_LU;
+
+    let mut levels: Vec<f64> = audible().map(lufs).filter(|&l| l > threshold).collect();
+    if levels.is_empty() {
+        return 0.0;
+    }
+    levels.sort_by(f64::total_cmp);
+
+    let percentile = |p: f64| levels[((levels.len() - 1) as f64 * p).round() as usize];
+    percentile(0.95) - percentile(0.10)
+}
+
+/// BS.1770 channel weights: surround channels count for more and the LFE not at all.
+/// Channels are assumed to be in the usual L, R, C, Ls, Rs order for 5.0 and
+/// L, R, C, LFE, Ls, Rs for 5.1.
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (6, 3) => 0.0,
+        (5, 3..=4) | (6, 4..=5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// The high shelf and high pass stages of the K-weighting curve, tuned to the sample rate
+struct KWeighting {
+    stages: [Biquad; 2],
+}
+
+impl KWeighting {
+    fn new(sample_rate: f64) -> Self {
+        // Pre-filter: high shelf modelling the acoustic effect of the head
+        let f0 = 1681.974450955533;
+        let gain_db = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / sample_rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        // RLB weighting: high pass
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        KWeighting {
+            stages: [shelf, high_pass],
+        }
+    }
+
+    fn process(&mut self, sample: f64) -> f64 {
+        self.stages
+            .iter_mut()
+            .fold(sample, |sample, stage| stage.process(sample))
+    }
+}
+
+/// Estimates inter-sample peaks by interpolating each channel to four times its rate
+struct Upsampler {
+    /// Windowed-sinc interpolation filter, split into one set of taps per output phase
+    phases: [[f64; TAPS_PER_PHASE]; OVERSAMPLING],
+    history: [f64; TAPS_PER_PHASE],
+    next: usize,
+}
+
+impl Upsampler {
+    fn new() -> Self {
+        let len = OVERSAMPLING * TAPS_PER_PHASE;
+        let center = (len - 1) as f64 / 2.0;
+        let mut phases = [[0.0; TAPS_PER_PHASE]; OVERSAMPLING];
+
+        for (i, tap) in (0..len).map(|i| (i, i as f64 - center)) {
+            let x = tap / OVERSAMPLING as f64;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            let window = 0.5 - 0.5 * (2.0 * PI * i as f64 / (len - 1) as f64).cos();
+            phases[i % OVERSAMPLING][i / OVERSAMPLING] = sinc * window;
+        }
+
+        // Each phase passes DC unchanged
+        for phase in &mut phases {
+            let sum: f64 = phase.iter().sum();
+            phase.iter_mut().for_each(|tap| *tap /= sum);
+        }
+
+        Upsampler {
+            phases,
+            history: [0.0; TAPS_PER_PHASE],
+            next: 0,
+        }
+    }
+
+    /// Takes the next sample and returns the highest absolute value around it
+    fn peak(&mut self, sample: f64) -> f64 {
+        self.history[self.next] = sample;
+        self.next = (self.next + 1) % TAPS_PER_PHASE;
+
+        let mut peak = sample.abs();
+        for phase in &self.phases {
+            // Newest sample meets the last tap
+            let value: f64 = (0..TAPS_PER_PHASE)
+                .map(|t| phase[t] * self.history[(self.next + t) % TAPS_PER_PHASE])
+                .sum();
+            peak = peak.max(value.abs());
+        }
+        peak
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Integrated loudness of 5s of a 997Hz sine at `dbfs` on every channel but the
+    /// silent ones
+    fn sine_lufs(channels: u16, dbfs: f64, silent: &[usize]) -> Option<f64> {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        let mut meter = Meter::new(channels, SAMPLE_RATE);
+        let samples: Vec<f32> = (0..5 * SAMPLE_RATE as usize)
+            .flat_map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                let sample = (amplitude * (2.0 * PI * 997.0 * t).sin()) as f32;
+                (0..channels as usize).map(move |c| if silent.contains(&c) { 0.0 } else { sample })
+            })
+            .collect();
+        meter.add_samples(&samples);
+        meter.loudness().integrated
+    }
+
+    /// A full scale sine on one front channel reads -3.01 LUFS; louder channels add up in power
+    fn expected(dbfs: f64, weights: f64) -> f64 {
+        -3.01 + dbfs + 10.0 * weights.log10()
+    }
+
+    fn assert_lufs(measured: Option<f64>, expected: f64) {
+        let measured = measured.expect("the sine should be above the gates");
+        // The tolerance EBU Tech 3341 allows
+        assert!(
+            (measured - expected).abs() <= 0.1,
+            "measured {measured:.2} LUFS, expected {expected:.2} LUFS"
+        );
+    }
+
+    #[test]
+    fn tech_3341_stereo_sines() {
+        // Test cases 1 and 2
+        assert_lufs(sine_lufs(2, -23.0, &[]), -23.0);
+        assert_lufs(sine_lufs(2, -33.0, &[]), -33.0);
+    }
+
+    #[test]
+    fn mono_sine() {
+        assert_lufs(sine_lufs(1, -20.0, &[]), expected(-20.0, 1.0));
+    }
+
+    #[test]
+    fn surround_channels_weigh_more_in_5_0() {
+        // L, R and C at 1.0, Ls and Rs at 1.41
+        assert_lufs(sine_lufs(5, -30.0, &[]), expected(-30.0, 3.0 + 2.0 * 1.41));
+        assert_lufs(sine_lufs(5, -30.0, &[0, 1, 2, 4]), expected(-30.0, 1.41));
+    }
+
+    #[test]
+    fn lfe_is_left_out_of_5_1() {
+        assert_lufs(sine_lufs(6, -30.0, &[]), expected(-30.0, 3.0 + 2.0 * 1.41));
+        assert_eq!(sine_lufs(6, -30.0, &[0, 1, 2, 4, 5]), None);
+        assert_lufs(sine_lufs(6, -30.0, &[0, 1, 2, 3, 5]), expected(-30.0, 1.41));
+    }
+
+    #[test]
+    fn true_peak_of_silence_is_none() {
+        let mut meter = Meter::new(2, SAMPLE_RATE);
+        meter.add_samples(&vec![0.0; 2 * SAMPLE_RATE as usize]);
+        let loudness = meter.loudness();
+        assert_eq!(loudness.integrated, None);
+        assert_eq!(loudness.true_peak_db(), None);
+    }
+
+    #[test]
+    fn true_peak_of_half_scale_sine_is_minus_6_db() {
+        let mut meter = Meter::new(1, SAMPLE_RATE);
+        let samples: Vec<f32> = (0..SAMPLE_RATE)
+            .map(|i| (0.5 * (2.0 * PI * 997.0 * i as f64 / SAMPLE_RATE as f64).sin()) as f32)
+            .collect();
+        meter.add_samples(&samples);
+        let peak = meter.loudness().true_peak_db().unwrap();
+        assert!((peak - -6.02).abs() < 0.05, "{peak:.3} dBTP");
+    }
+}
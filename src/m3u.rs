@@ -1,16 +1,24 @@
+use http_body_util::BodyExt;
 use url::Url;
 
-use crate::{err, play_music::{self, play_music}, play_url};
-use std::{fs, path::Path, process::exit};
+use crate::error::{Error, Result};
+use crate::player::hls::{head_is_hls, is_hls};
+use crate::player::url::{client, fetch, get};
+use crate::track::Track;
+use std::{fs, path::Path};
 
-struct M3uEntry {
-    path: String,
-    title: Option<String>,
-    #[allow(dead_code)]
-    duration: Option<i32>,
+/// One entry of an M3U or PLS playlist, as written in the file
+pub struct PlaylistEntry {
+    pub path: String,
+    /// Title from the preceding `#EXTINF` line, or from `TitleN` in a PLS
+    pub title: Option<String>,
+    /// Length in seconds from the preceding `#EXTINF` line, or from `LengthN` in a PLS;
+    /// `-1` marks a stream
+    pub duration: Option<i32>,
 }
 
-fn parse(m3u: &str) -> Vec<M3uEntry> {
+/// Parses M3U or extended M3U text. Paths are returned as written, without resolving them.
+pub fn parse(m3u: &str) -> Vec<PlaylistEntry> {
     let mut entries = Vec::new();
     let mut current_title = None;
     let mut current_duration = None;
@@ -21,7 +29,7 @@ fn parse(m3u: &str) -> Vec<M3uEntry> {
             .lines()
             .map(|line| line.trim())
             .filter(|line| !line.is_empty() && !line.starts_with('#'))
-            .map(|line| M3uEntry {
+            .map(|line| PlaylistEntry {
                 path: line.to_string(),
                 title: None,
                 duration: None,
@@ -36,7 +44,7 @@ fn parse(m3u: &str) -> Vec<M3uEntry> {
             current_duration = parts.next().and_then(|s| s.parse::<i32>().ok());
             current_title = parts.next().map(String::from);
         } else if !line.starts_with('#') {
-            entries.push(M3uEntry {
+            entries.push(PlaylistEntry {
                 path: line.to_string(),
                 title: current_title.take(),
                 duration: current_duration.take(),
@@ -46,33 +54,158 @@ fn parse(m3u: &str) -> Vec<M3uEntry> {
     entries
 }
 
-pub async fn play_m3u<P: AsRef<Path>>(path: P, volume: f32, gui: bool) {
+/// Reads an M3U playlist, resolving relative entries against the playlist's directory
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Track>> {
     let path = path.as_ref();
-    let content = fs::read_to_string(path).unwrap_or_else(|e| {
-        err!("Failed to read m3u file: {}", e);
-        exit(1);
-    });
-
-    for entry in parse(&content) {
-        if let Ok(url) = Url::parse(&entry.path) {
-            if let Ok(url_file) = url.to_file_path() {
-                play_music(url_file.to_string_lossy().to_string(), volume, gui, entry.title.clone()).await;
-                continue;
+    let content = fs::read_to_string(path)?;
+    Ok(resolve(parse(&content), path))
+}
+
+/// Turns the entries of the playlist file at `path` into tracks, resolving relative entries
+/// against its directory
+pub(crate) fn resolve(entries: Vec<PlaylistEntry>, path: &Path) -> Vec<Track> {
+    entries
+        .into_iter()
+        .map(|entry| {
+            if Url::parse(&entry.path).is_ok() || Path::new(&entry.path).is_absolute() {
+                return Track::new(entry.path, entry.title);
             }
-            play_url::play_url(&entry.path, volume, entry.title).await;
-            continue;
-        }
 
-        let file_path = if Path::new(&entry.path).is_absolute() {
-            entry.path
-        } else {
-            path.parent()
+            let file_path = path
+                .parent()
                 .unwrap_or_else(|| Path::new("."))
                 .join(&entry.path)
                 .to_string_lossy()
-                .to_string()
-        };
+                .to_string();
+
+            Track::new(file_path, entry.title)
+        })
+        .collect()
+}
+
+/// Downloads an M3U playlist, resolving relative entries against the URL it finally came
+/// from. An HLS playlist is one stream rather than a list, so it comes back as itself; its
+/// first lines tell it apart, and the rest is left for the player to fetch.
+pub async fn load_url(url: &str) -> Result<Vec<Track>> {
+    let (response, from) = get(&client(), url, &[]).await.map_err(Error::Stream)?;
+    let mut body = response.into_body();
+    let mut content = Vec::new();
+    let mut hls = None;
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|e| Error::Stream(e.into()))?;
+        if let Some(chunk) = frame.data_ref() {
+            content.extend_from_slice(chunk);
+        }
+        if hls.is_none() {
+            hls = head_is_hls(&String::from_utf8_lossy(&content));
+        }
+        if hls == Some(true) {
+            return Ok(vec![Track::new(url, None)]);
+        }
+    }
+
+    let content = String::from_utf8_lossy(&content);
+    if hls.is_none() && is_hls(&content) {
+        return Ok(vec![Track::new(url, None)]);
+    }
+    let base = Url::parse(&from).map_err(|e| Error::Stream(e.into()))?;
+    Ok(resolve_url(parse(&content), &base))
+}
+
+/// Downloads a playlist, returning its text and the URL it finally came from
+pub(crate) async fn download(url: &str) -> Result<(String, Url)> {
+    let (body, from) = fetch(&client(), url).await.map_err(Error::Stream)?;
+    let base = Url::parse(&from).map_err(|e| Error::Stream(e.into()))?;
+    Ok((String::from_utf8_lossy(&body).to_string(), base))
+}
+
+/// Turns the entries of a playlist downloaded from `base` into tracks, resolving relative
+/// entries against it
+pub(crate) fn resolve_url(entries: Vec<PlaylistEntry>, base: &Url) -> Vec<Track> {
+    entries
+        .into_iter()
+        .filter_map(|entry| {
+            let location = base.join(&entry.path).ok()?;
+            Some(Track::new(location.as_str(), entry.title))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extinf_titles_and_lengths() {
+        let entries = parse(
+            "#EXTM3U
+#EXTINF:215,Artist - Song, with a comma
+song.mp3
+#EXTINF:-1,Radio
+http://radio.example.com/stream
+#EXTINF:bad,Untimed
+untimed.mp3
+plain.mp3
+",
+        );
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].path, "song.mp3");
+        assert_eq!(
+            entries[0].title.as_deref(),
+            Some("Artist - Song, with a comma")
+        );
+        assert_eq!(entries[0].duration, Some(215));
+        assert_eq!(entries[1].title.as_deref(), Some("Radio"));
+        assert_eq!(entries[1].duration, Some(-1));
+        assert_eq!(entries[2].title.as_deref(), Some("Untimed"));
+        assert_eq!(entries[2].duration, None);
+        // An #EXTINF line only belongs to the entry right after it
+        assert_eq!(entries[3].title, None);
+        assert_eq!(entries[3].duration, None);
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let extended = parse("#EXTM3U\n\n# a comment\n  a.mp3  \n\n#EXTVLCOPT:x\nb.mp3\n");
+        let paths: Vec<_> = extended.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["a.mp3", "b.mp3"]);
+
+        // Without the header, only paths count
+        let simple = parse("# list\na.mp3\n\n   \n#EXTINF:10,Ignored\nb.mp3");
+        assert_eq!(simple.len(), 2);
+        assert!(simple.iter().all(|e| e.title.is_none()));
+    }
+
+    #[test]
+    fn relative_paths_follow_the_playlist() {
+        let entries = parse("a.mp3\nsub/b.mp3\n/abs/c.mp3\nhttp://example.com/d.mp3\n");
+        let tracks = resolve(entries, Path::new("/music/lists/mix.m3u"));
+        let locations: Vec<_> = tracks.iter().map(|t| t.location.as_str()).collect();
+        assert_eq!(
+            locations,
+            [
+                "/music/lists/a.mp3",
+                "/music/lists/sub/b.mp3",
+                "/abs/c.mp3",
+                "http://example.com/d.mp3",
+            ]
+        );
+    }
 
-        play_music::play_music(file_path, volume, gui, entry.title).await;
+    #[test]
+    fn relative_urls_follow_the_playlist_url() {
+        let entries = parse("a.mp3\n../b.mp3\n/c.mp3\nhttps://other.example.com/d.mp3\n");
+        let base = Url::parse("https://example.com/lists/mix.m3u").unwrap();
+        let tracks = resolve_url(entries, &base);
+        let locations: Vec<_> = tracks.iter().map(|t| t.location.as_str()).collect();
+        assert_eq!(
+            locations,
+            [
+                "https://example.com/lists/a.mp3",
+                "https://example.com/b.mp3",
+                "https://example.com/c.mp3",
+                "https://other.example.com/d.mp3",
+            ]
+        );
     }
 }
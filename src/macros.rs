@@ -5,3 +5,11 @@ macro_rules! err {
         eprintln!("{} {}", "Error:".red().bold(), format!($($msg), *).red());
     }};
 }
+
+#[macro_export]
+macro_rules! warn {
+    ($($msg: expr), *) => {{
+        use crossterm::style::Stylize;
+        eprintln!("{} {}", "Warning:".yellow().bold(), format!($($msg), *).yellow());
+    }};
+}
@@ -1,22 +1,49 @@
+mod directory;
 mod display_image;
 mod display_info;
+mod eq_presets;
 mod info;
 mod input;
-mod m3u;
 mod macros;
 mod play_music;
 mod play_url;
-mod player;
-use std::{path::Path, process::exit};
+mod queue;
+mod resume;
+mod scan_loudness;
+mod sleep_timer;
+use std::{
+    io::{self, IsTerminal},
+    path::Path,
+    process::exit,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use clap::Parser;
-use url::Url;
+use clap::{Parser, Subcommand, ValueEnum};
+use directory::SortOrder;
+use eq_presets::EqPresets;
+use minau::error::Error;
+use minau::player::eq::Band;
+use minau::player::output::{self, Output, OutputConfig};
+use minau::player::play::MAX_DECLICK;
+use minau::player::replaygain::{GainMode, ReplayGain};
+use minau::player::stereo::StereoControl;
+use minau::player::stretch::{MAX_SPEED, MIN_SPEED, SpeedControl};
+use minau::track::Track;
+use minau::{m3u, pls, xspf};
+use play_music::Settings;
+use queue::{Command, Queue, Repeat, Shuffle};
+use resume::Positions;
+use sleep_timer::{Sleep, SleepTimer};
 
 #[derive(Parser)]
 #[command(name = env!("CARGO_PKG_NAME"))]
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(about = env!("CARGO_PKG_DESCRIPTION"))]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<SubCommand>,
     /// Files to play (multiple selections allowed)
     files: Vec<String>,
     /// Specify the default playback volume (minimum: 1, maximum: 100)
@@ -25,6 +52,121 @@ struct Cli {
     /// Display album art in a GUI
     #[arg(short, long)]
     gui: bool,
+    /// Crossfade consecutive tracks over the given number of seconds
+    #[arg(long, value_name = "SECS")]
+    crossfade: Option<f32>,
+    /// Play in random order without repeating a track until all have played
+    #[arg(
+        short,
+        long,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "track"
+    )]
+    shuffle: Option<Shuffle>,
+    /// Order of the files found in directory arguments
+    #[arg(long, value_enum, default_value_t = SortOrder::Natural)]
+    sort: SortOrder,
+    /// Include hidden files and directories when expanding directories
+    #[arg(long)]
+    hidden: bool,
+    /// Repeat the current track or the whole queue
+    #[arg(short, long, value_enum, default_value_t = Repeat::None)]
+    repeat: Repeat,
+    /// Normalize loudness with ReplayGain tags, applied on top of the volume
+    #[arg(long, value_enum, default_value_t = ReplayGainMode::Off)]
+    replaygain: ReplayGainMode,
+    /// Extra gain in dB added to every ReplayGain value
+    #[arg(
+        long,
+        value_name = "DB",
+        default_value_t = 0.0,
+        allow_negative_numbers = true
+    )]
+    replaygain_preamp: f32,
+    /// Add an equalizer band, e.g. 2700:-3:2 or low:105:4.5 (repeatable)
+    #[arg(
+        long,
+        value_name = "[peak:|low:|high:]FREQ:GAIN[:Q]",
+        allow_negative_numbers = true
+    )]
+    eq: Vec<Band>,
+    /// File of named equalizer presets that the e key switches between
+    #[arg(long, value_name = "FILE")]
+    eq_presets: Option<String>,
+    /// Preset to start with: flat, custom (the --eq bands) or a name from the presets file
+    #[arg(long, value_name = "NAME")]
+    eq_preset: Option<String>,
+    /// Playback speed from 0.5 to 3, keeping the pitch; [ and ] change it while playing
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0)]
+    speed: f32,
+    /// Start each file where it was last quit or skipped, unless that was near its end
+    #[arg(long)]
+    resume: bool,
+    /// Stop after a duration such as 45m or "1h 30m", or at the end of the current track with "track"
+    #[arg(long, value_name = "DURATION|track")]
+    sleep: Option<Sleep>,
+    /// How long the volume fades out before the sleep timer stops playback
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "10s",
+        value_parser = humantime::parse_duration
+    )]
+    sleep_fade: Duration,
+    /// How long pausing, resuming, skipping and stopping fade for, so they never click;
+    /// at most 200ms
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "20ms",
+        value_parser = humantime::parse_duration
+    )]
+    declick: Duration,
+    /// Render to a WAV file instead of the sound card, or discard the audio with "null"
+    #[arg(long, value_name = "FILE|null")]
+    output: Option<String>,
+    /// List the available output devices and exit
+    #[arg(long)]
+    list_devices: bool,
+    /// Output device, by name or by index as shown by --list-devices
+    #[arg(long, value_name = "NAME|INDEX", conflicts_with = "output")]
+    device: Option<String>,
+    /// Sample rate of the output stream in Hz
+    #[arg(long, value_name = "HZ", conflicts_with = "output")]
+    sample_rate: Option<u32>,
+    /// Buffer size of the output stream in frames
+    #[arg(long, value_name = "FRAMES", conflicts_with = "output")]
+    buffer_size: Option<u32>,
+    /// Print plain track-change lines without key controls (the default when not run in a terminal)
+    #[arg(long)]
+    no_tui: bool,
+}
+
+#[derive(Subcommand)]
+enum SubCommand {
+    /// Measure EBU R128 loudness, loudness range and true peak; each directory is an album
+    ScanLoudness {
+        /// Files and directories to scan
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Store the results as ReplayGain 2.0 tags (-18 LUFS reference)
+        #[arg(long)]
+        write_tags: bool,
+        /// Include hidden files and directories when expanding directories
+        #[arg(long)]
+        hidden: bool,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ReplayGainMode {
+    Off,
+    /// Gain of each track on its own
+    Track,
+    /// Gain of the album, keeping the loudness differences within it
+    Album,
 }
 
 const DEFAULT_VOLUME: u16 = 100;
@@ -35,6 +177,31 @@ const MAX_VOLUME: u16 = 100;
 async fn main() {
     let args = Cli::parse();
 
+    // Escape sequences and raw mode only make sense on an interactive terminal
+    let tui = !args.no_tui && io::stdin().is_terminal() && io::stdout().is_terminal();
+    if !tui {
+        crossterm::style::force_color_output(false);
+    }
+
+    if let Some(SubCommand::ScanLoudness {
+        paths,
+        write_tags,
+        hidden,
+    }) = &args.command
+    {
+        let failed = scan_loudness::scan_loudness(paths, *write_tags, *hidden);
+        if failed > 0 {
+            err!("{} files could not be scanned or tagged", failed);
+            exit(1);
+        }
+        return;
+    }
+
+    if args.list_devices {
+        list_devices();
+        return;
+    }
+
     let volume = args
         .volume
         .map(|vol| {
@@ -50,30 +217,184 @@ async fn main() {
             exit(1);
         });
 
+    let crossfade = args
+        .crossfade
+        .map(|secs| Duration::try_from_secs_f32(secs).map_err(|_| secs))
+        .unwrap_or(Ok(Duration::ZERO))
+        .unwrap_or_else(|secs| {
+            err!("{} is not available crossfade length", secs);
+            exit(1);
+        });
+
+    if !(MIN_SPEED..=MAX_SPEED).contains(&args.speed) {
+        err!("{} is not available speed", args.speed);
+        exit(1);
+    }
+
+    let declick = args.declick.min(MAX_DECLICK);
+    if declick < args.declick {
+        warn!(
+            "--declick is limited to {}",
+            humantime::format_duration(MAX_DECLICK)
+        );
+    }
+
+    let replay_gain = match args.replaygain {
+        ReplayGainMode::Off => None,
+        ReplayGainMode::Track => Some(GainMode::Track),
+        ReplayGainMode::Album => Some(GainMode::Album),
+    }
+    .map(|mode| ReplayGain {
+        mode,
+        preamp: args.replaygain_preamp,
+    });
+
+    let presets = match &args.eq_presets {
+        Some(path) => eq_presets::load(path),
+        None => Ok(Vec::new()),
+    };
+    let eq = presets
+        .and_then(|presets| EqPresets::new(args.eq, presets, args.eq_preset.as_deref()))
+        .unwrap_or_else(|e| {
+            err!("{}", e);
+            exit(1);
+        });
+
+    let settings = Settings {
+        volume,
+        gui: args.gui,
+        crossfade,
+        tui,
+        replay_gain,
+        eq: Arc::new(Mutex::new(eq)),
+        speed: SpeedControl::new(args.speed),
+        stereo: StereoControl::new(),
+        resume: args.resume,
+        positions: Arc::new(Mutex::new(Positions::load())),
+        sleep_timer: Arc::new(Mutex::new(SleepTimer::new(args.sleep, args.sleep_fade))),
+        declick,
+    };
+
     if args.files.is_empty() {
         err!("Music file is not specified!");
         exit(1);
     }
 
+    let mut failed: Vec<(String, Error)> = Vec::new();
+    let mut tracks = Vec::new();
     for path in args.files {
-        let path_extens: &Path = path.as_ref();
-        if let Some(ext) = path_extens.extension()
-            && (ext == "m3u" || ext == "m3u8")
-        {
-            m3u::play_m3u(&path, volume, args.gui).await;
+        let track = Track::new(path, None);
+        let playlist = match track.extension().as_deref() {
+            Some("m3u" | "m3u8") if track.is_remote() => Some(m3u::load_url(&track.location).await),
+            Some("m3u" | "m3u8") => Some(m3u::load(&track.location)),
+            Some("pls") if track.is_remote() => Some(pls::load_url(&track.location).await),
+            Some("pls") => Some(pls::load(&track.location)),
+            Some("xspf") if track.is_remote() => Some(xspf::load_url(&track.location).await),
+            Some("xspf") => Some(xspf::load(&track.location)),
+            _ => None,
+        };
+        if let Some(playlist) = playlist {
+            match playlist {
+                Ok(entries) => tracks.extend(entries),
+                Err(e) => {
+                    warn!("Skipping {}: {}", track.location, e);
+                    failed.push((track.location, e));
+                }
+            }
             continue;
         }
 
-        let bind = path.clone();
-        if let Ok(url) = Url::parse(&bind) {
-            if let Ok(file_url) = url.to_file_path() {
-                play_music::play_music(file_url.to_string_lossy().to_string(), volume, args.gui, None).await;
-                continue;
-            }
-            play_url::play_url(&bind, volume, None).await;
+        if Path::new(&track.location).is_dir() {
+            tracks.extend(directory::expand(&track.location, args.sort, args.hidden));
             continue;
-        } 
+        }
+
+        tracks.push(track);
+    }
+
+    let output = match args.output.as_deref() {
+        None => Output::open(&OutputConfig {
+            device: args.device,
+            sample_rate: args.sample_rate,
+            buffer_size: args.buffer_size,
+        }),
+        Some("null") => Ok(Output::null()),
+        Some(path) => Output::wav(path),
+    };
+    let output = output.unwrap_or_else(|e| {
+        err!("{}", e);
+        exit(1);
+    });
+    let queue = Arc::new(Mutex::new(Queue::new(tracks, args.shuffle, args.repeat)));
+    let queue_len = queue.lock().unwrap().len();
+    let mut failures_in_a_row = 0;
+
+    loop {
+        let Some(track) = queue.lock().unwrap().current().cloned() else {
+            break;
+        };
+
+        let result = if track.is_remote() {
+            play_url::play_url(
+                output.mixer(),
+                &track.location,
+                track.display_title(),
+                track.duration,
+                &queue,
+                &settings,
+            )
+            .await
+        } else {
+            // Consecutive local files share one output so they join without a gap
+            play_music::play_music(output.mixer(), &queue, &settings).await
+        };
+
+        match result {
+            Ok(command) => {
+                failures_in_a_row = 0;
+                queue.lock().unwrap().apply(command);
+            }
+            Err(e) => {
+                warn!("Skipping {}: {}", track.location, e);
+                if !failed
+                    .iter()
+                    .any(|(location, _)| *location == track.location)
+                {
+                    failed.push((track.location, e));
+                }
+
+                // With repeat on, a queue where nothing plays would otherwise loop forever
+                failures_in_a_row += 1;
+                if failures_in_a_row >= queue_len {
+                    break;
+                }
+                queue.lock().unwrap().apply(Command::Next);
+            }
+        }
+    }
+
+    if let Err(e) = output.finish() {
+        err!("{}", e);
+        exit(1);
+    }
+
+    if !failed.is_empty() {
+        err!("{} entries could not be played:", failed.len());
+        for (location, e) in &failed {
+            eprintln!("  {}: {}", location, e);
+        }
+        exit(1);
+    }
+}
+
+fn list_devices() {
+    let devices = output::devices().unwrap_or_else(|e| {
+        err!("{}", e);
+        exit(1);
+    });
 
-        play_music::play_music(&path, volume, args.gui, None).await;
+    for (i, device) in devices.iter().enumerate() {
+        let default = if device.is_default { " (default)" } else { "" };
+        println!("{}: {}{}", i, device.name, default);
     }
 }
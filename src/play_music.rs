@@ -1,75 +1,180 @@
 use crate::display_info::string_info;
+use crate::eq_presets::EqPresets;
 use crate::input::{deinit, get_input};
-use crate::player::metadata::MetaData;
-use crate::player::player_structs::Player;
-use crate::{display_image, display_info};
+use crate::queue::{Command, Queue};
+use crate::resume::Positions;
+use crate::sleep_timer::SleepTimer;
+use crate::{display_image, display_info, warn};
 use crossterm::cursor::MoveToPreviousLine;
 use crossterm::terminal::ClearType;
 use crossterm::terminal::{Clear, SetTitle};
 use crossterm::{execute, terminal};
 use humantime::format_duration;
 use indicatif::{ProgressBar, ProgressStyle};
+use minau::error::{Error, Result};
+use minau::player::metadata::MetaData;
+use minau::player::play::{AbLoop, MusicPlay, SKIP_POLL_INTERVAL, when_silent};
+use minau::player::player_structs::Player;
+use minau::player::replaygain::ReplayGain;
+use minau::player::stereo::StereoControl;
+use minau::player::stretch::SpeedControl;
+use minau::track::Track;
+use rodio::mixer::Mixer;
 use std::env;
 use std::io::{Write, stdout};
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, mpsc};
 use std::time::Duration;
 use tokio::time::sleep;
 use unicode_width::UnicodeWidthStr;
 
 const TICK_INTERVAL_MS: u64 = 100;
 const TICKS_PER_SECOND: u32 = 4;
+/// How long before the current track (or its fade) ends the next one is looked up and loaded.
+/// Shuffle and repeat changes made before this point still decide what comes next.
+const PRELOAD_AHEAD: Duration = Duration::from_secs(3);
 
-pub async fn play_music<P: AsRef<Path>>(
-    path: P,
-    volume: f32,
-    gui: bool,
-    title_override: Option<String>,
-) {
-    let player = Player::new(&path);
-    let mut metadata = player.metadata();
-    if let Some(title) = title_override {
-        metadata.set_title(Some(title));
+/// Everything the display needs to know about the track that is currently audible
+struct NowPlaying {
+    track: Track,
+    metadata: MetaData,
+    filename: String,
+    path: String,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl NowPlaying {
+    fn load(track: &Track, settings: &Settings) -> Result<(Player, Self)> {
+        let path = Path::new(&track.location);
+        let mut player = Player::new(path)?;
+        let mut metadata = player.metadata()?;
+        if let Some(title) = track.title.clone() {
+            metadata.set_title(Some(title));
+        }
+        metadata.set_artist(track.artist.clone());
+        metadata.set_album(track.album.clone());
+        if let Some(replay_gain) = settings.replay_gain {
+            player.set_gain(replay_gain.factor(&metadata));
+        }
+        player.set_equalizer(settings.eq.lock().unwrap().control().clone());
+        player.set_speed(settings.speed.clone());
+        player.set_stereo(settings.stereo.clone());
+        let resume_at = settings
+            .resume
+            .then(|| {
+                settings
+                    .positions
+                    .lock()
+                    .unwrap()
+                    .get(&track.location, metadata.duration())
+            })
+            .flatten();
+        if let Some(pos) = resume_at {
+            // A file that cannot seek simply starts from the beginning
+            let _ = player.seek(pos);
+        }
+
+        let filename = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .to_string();
+
+        let now = NowPlaying {
+            track: track.clone(),
+            metadata,
+            filename,
+            path: path.display().to_string(),
+            sample_rate: player.sample_rate(),
+            channels: player.channels(),
+        };
+        Ok((player, now))
     }
-    let close_gui = Arc::new(Mutex::new(false));
+}
 
-    let filename = path
-        .as_ref()
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
+enum TrackEnd {
+    /// The next track has taken over the sink
+    Next(Box<NowPlaying>),
+    /// Playback on this sink is over and the queue should act on the command
+    Stop(Command),
+    /// Playback could not go on
+    Failed(Error),
+}
 
-    let path_display = path.as_ref().display().to_string();
+/// The track loaded to follow the current one
+struct Upcoming {
+    now: NowPlaying,
+    handover: Handover,
+}
 
-    set_terminal_title(&filename, &metadata);
+/// How the upcoming track takes over from the current one
+enum Handover {
+    /// Already appended to the sink right behind the current track
+    Gapless,
+    /// Started on a sink of its own once the current track reaches the fade window
+    Crossfade(Player, Duration),
+}
 
-    let value = metadata.clone();
-    let file_clone = filename.clone();
-    let player_bind = player.clone();
+/// Playback settings given on the command line
+#[derive(Clone)]
+pub struct Settings {
+    pub volume: f32,
+    /// Display album art in a window
+    pub gui: bool,
+    pub crossfade: Duration,
+    /// Key controls and a progress bar; without them every track change is a plain line
+    pub tui: bool,
+    pub replay_gain: Option<ReplayGain>,
+    pub eq: Arc<Mutex<EqPresets>>,
+    pub speed: SpeedControl,
+    /// Balance, mono downmix and channel swap
+    pub stereo: StereoControl,
+    /// Start files where they were last left off
+    pub resume: bool,
+    pub positions: Arc<Mutex<Positions>>,
+    pub sleep_timer: Arc<Mutex<SleepTimer>>,
+    /// Ramp that pausing, resuming, skipping and stopping fade over
+    pub declick: Duration,
+}
 
-    let bind = path_display.clone();
-    let bind_clg = Arc::clone(&close_gui);
+/// Plays the current track of `queue` and every local file that follows it on one output:
+/// the next decoder is queued shortly before the current one ends, or crossfaded in when
+/// a crossfade is set. Returns what the queue should do once this run is over.
+pub async fn play_music(
+    mixer: &Mixer,
+    queue: &Arc<Mutex<Queue>>,
+    settings: &Settings,
+) -> Result<Command> {
+    let (gui_tx, gui_rx) = mpsc::channel::<(MetaData, String, Arc<Mutex<bool>>)>();
+
+    let mixer = mixer.clone();
+    let queue = Arc::clone(queue);
+    let thread_settings = settings.clone();
     let play_thread = std::thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(async {
-            really_play(player_bind, value, file_clone, bind, volume).await;
-            let mut clg = bind_clg.lock().unwrap();
-            *clg = true;
-        });
+        rt.block_on(really_play(mixer, queue, thread_settings, gui_tx))
     });
 
-    if gui && let Some(pic) = metadata.picture() {
-        if env::var("WAYLAND_DISPLAY").is_ok() {
-            unsafe { env::remove_var("WAYLAND_DISPLAY") };
+    for (metadata, filename, close_gui) in gui_rx {
+        if settings.gui
+            && let Some(pic) = metadata.picture()
+        {
+            if env::var("WAYLAND_DISPLAY").is_ok() {
+                unsafe { env::remove_var("WAYLAND_DISPLAY") };
+            }
+            if let Err(e) = display_image::display(pic, &filename, metadata, close_gui) {
+                warn!("{}", e);
+            }
         }
-        display_image::display(pic, &filename, metadata, close_gui);
     }
 
-    play_thread.join().unwrap();
+    let command = play_thread.join().unwrap();
 
-    reset_terminal_title();
+    if settings.tui {
+        reset_terminal_title();
+    }
+    command
 }
 
 fn set_terminal_title(filename: &str, metadata: &MetaData) {
@@ -84,49 +189,267 @@ fn reset_terminal_title() {
 }
 
 async fn really_play(
-    player: Player,
-    metadata: MetaData,
-    filename: String,
-    path: String,
-    volume: f32,
-) {
-    let sample_rate_khz = player.sample_rate() as f32 / 1000.0;
-    let duration = metadata.duration();
-
-    println!(
-        "{}kHz/{}ch | {}",
-        sample_rate_khz,
-        player.channels(),
-        format_duration(Duration::from_secs(duration.as_secs()))
-    );
-    crate::display_info::display_info(&filename, &metadata);
+    mixer: Mixer,
+    queue: Arc<Mutex<Queue>>,
+    settings: Settings,
+    gui_tx: mpsc::Sender<(MetaData, String, Arc<Mutex<bool>>)>,
+) -> Result<Command> {
+    let Some(track) = queue.lock().unwrap().current().cloned() else {
+        return Ok(Command::Next);
+    };
+    let (player, mut now) = NowPlaying::load(&track, &settings)?;
+    let music_play = Arc::new(Mutex::new(
+        player
+            .play(&mixer)
+            .set_volume(settings.volume)
+            .set_declick(settings.declick),
+    ));
+
+    loop {
+        let close_gui = Arc::new(Mutex::new(false));
+        let _ = gui_tx.send((
+            now.metadata.clone(),
+            now.filename.clone(),
+            Arc::clone(&close_gui),
+        ));
+
+        let end = play_track(&music_play, &now, &queue, &settings).await;
+        *close_gui.lock().unwrap() = true;
+
+        match end {
+            TrackEnd::Next(next) => now = *next,
+            // Fade out whatever is still playing instead of cutting it off mid-buffer
+            TrackEnd::Stop(command) => {
+                when_silent(&music_play, MusicPlay::stop).await;
+                return Ok(command);
+            }
+            TrackEnd::Failed(e) => {
+                when_silent(&music_play, MusicPlay::stop).await;
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Loads what `command` leads to if it is a local file, deciding how it takes over.
+/// A track that fails to load is left for the queue to report when it gets there.
+fn load_upcoming(
+    music_play: &Mutex<MusicPlay>,
+    now: &NowPlaying,
+    queue: &Mutex<Queue>,
+    command: Command,
+    settings: &Settings,
+) -> Option<Upcoming> {
+    let track = queue
+        .lock()
+        .unwrap()
+        .peek(command)
+        .filter(|track| !track.is_remote())
+        .cloned()?;
+    let (player, next) = NowPlaying::load(&track, settings).ok()?;
+
+    // Never fade over more than half of either track
+    let fade = settings
+        .crossfade
+        .min(now.metadata.duration() / 2)
+        .min(next.metadata.duration() / 2);
+
+    let handover = if fade.is_zero() {
+        music_play.lock().unwrap().append(player);
+        Handover::Gapless
+    } else {
+        Handover::Crossfade(player, fade)
+    };
+
+    Some(Upcoming {
+        now: next,
+        handover,
+    })
+}
+
+async fn play_track(
+    music_play: &Arc<Mutex<MusicPlay>>,
+    now: &NowPlaying,
+    queue: &Arc<Mutex<Queue>>,
+    settings: &Settings,
+) -> TrackEnd {
+    let sample_rate_khz = now.sample_rate as f32 / 1000.0;
+    let duration = now.metadata.duration();
+    // A loop belongs to the track it was set in
+    music_play.lock().unwrap().clear_loop();
+
+    if settings.tui {
+        set_terminal_title(&now.filename, &now.metadata);
+        println!(
+            "{}kHz/{}ch | {}",
+            sample_rate_khz,
+            now.channels,
+            format_duration(Duration::from_secs(duration.as_secs()))
+        );
+        crate::display_info::display_info(&now.filename, &now.metadata);
+    } else {
+        println!(
+            "Playing: {} ({}kHz/{}ch, {})",
+            string_info(&now.filename, &now.metadata),
+            sample_rate_khz,
+            now.channels,
+            format_duration(Duration::from_secs(duration.as_secs()))
+        );
+    }
 
-    let music_play = Arc::new(Mutex::new(player.play().set_volume(volume)));
     let key_state = Arc::new(Mutex::new(false));
 
-    let key_thread = tokio::spawn(get_input(
-        Arc::clone(&music_play),
-        Arc::clone(&key_state),
-        filename.clone(),
-        path,
-        metadata.clone(),
-    ));
+    let mut key_thread = settings.tui.then(|| {
+        tokio::spawn(get_input(
+            Arc::clone(music_play),
+            Arc::clone(&key_state),
+            now.filename.clone(),
+            now.path.clone(),
+            now.metadata.clone(),
+            Arc::clone(queue),
+            settings.clone(),
+        ))
+    });
 
     let duration_secs = duration.as_secs();
-    let pb = create_progress_bar(duration_secs);
+    let pb = if settings.tui {
+        create_progress_bar(duration_secs)
+    } else {
+        ProgressBar::hidden()
+    };
 
     let mut tick_count = 0u32;
+    let mut upcoming: Option<Upcoming> = None;
+    let mut looked_ahead = false;
 
     loop {
-        if key_thread.is_finished() {
-            cleanup_and_exit(&pb, metadata, &filename);
-            return;
+        if let Some(key_thread) = key_thread.take_if(|thread| thread.is_finished()) {
+            let command = key_thread.await.unwrap_or(Ok(Command::Next));
+            cleanup_and_exit(&pb, now.metadata.clone(), &now.filename);
+
+            let command = match command {
+                Ok(command) => command,
+                Err(e) => return TrackEnd::Failed(e),
+            };
+
+            // Restarting asks for the beginning, anything else leaves the track to come back to
+            let left_at = music_play.lock().unwrap().get_pos();
+            save_position(
+                now,
+                (command != Command::Restart).then_some(left_at),
+                settings,
+            );
+
+            if command != Command::Next {
+                return TrackEnd::Stop(command);
+            }
+
+            // A track loaded ahead for the natural end can only be reused if skipping leads to it too
+            let target = queue.lock().unwrap().peek(Command::Next).cloned();
+            let upcoming = match upcoming {
+                Some(upcoming) if Some(&upcoming.now.track) == target.as_ref() => Some(upcoming),
+                Some(Upcoming {
+                    handover: Handover::Gapless,
+                    ..
+                }) => return TrackEnd::Stop(Command::Next),
+                _ => load_upcoming(music_play, now, queue, Command::Next, settings),
+            };
+            let Some(upcoming) = upcoming else {
+                return TrackEnd::Stop(Command::Next);
+            };
+
+            match upcoming.handover {
+                Handover::Gapless => skip_one(music_play).await,
+                Handover::Crossfade(player, fade) => {
+                    let mut play = music_play.lock().unwrap();
+                    let remaining = duration.saturating_sub(play.get_pos());
+                    let remaining = remaining.div_f32(settings.speed.get());
+                    play.crossfade_to(player, fade.min(remaining));
+                }
+            }
+            queue.lock().unwrap().apply(Command::Next);
+            return TrackEnd::Next(Box::new(upcoming.now));
         }
 
-        if music_play.lock().unwrap().is_empty() {
-            *key_state.lock().unwrap() = true;
-            cleanup_and_exit(&pb, metadata, &filename);
-            return;
+        let pos = music_play.lock().unwrap().get_pos();
+
+        let (asleep, sleep_at_end) = {
+            let mut timer = settings.sleep_timer.lock().unwrap();
+            let asleep =
+                timer.tick(|target, over| music_play.lock().unwrap().ramp_volume(target, over));
+            (asleep, timer.ends_with_track())
+        };
+        if asleep {
+            if let Some(key_thread) = key_thread {
+                *key_state.lock().unwrap() = true;
+                let _ = key_thread.await;
+                cleanup_and_exit(&pb, now.metadata.clone(), &now.filename);
+            }
+            save_position(now, Some(pos), settings);
+            return TrackEnd::Stop(Command::Quit);
+        }
+
+        let fade = match &upcoming {
+            Some(Upcoming {
+                handover: Handover::Crossfade(_, fade),
+                ..
+            }) => *fade,
+            _ => settings.crossfade,
+        };
+
+        // Fades and preloading take wall-clock time, during which this much of the track plays
+        let media = |wall: Duration| wall.mul_f32(settings.speed.get());
+
+        // A closed loop never lets the track reach its end
+        let looping = music_play.lock().unwrap().is_looping();
+
+        if !looked_ahead
+            && !looping
+            && !sleep_at_end
+            && pos + media(fade + PRELOAD_AHEAD) >= duration
+        {
+            looked_ahead = true;
+            upcoming = load_upcoming(music_play, now, queue, Command::Ended, settings);
+        }
+
+        let queued = match &upcoming {
+            Some(Upcoming {
+                handover: Handover::Gapless,
+                ..
+            }) => 2,
+            _ => 1,
+        };
+        let ended = music_play.lock().unwrap().queued() < queued;
+        // Stopping at the end of the track means letting it play out instead of fading over it
+        let fading = !sleep_at_end
+            && !looping
+            && matches!(
+                &upcoming,
+                Some(Upcoming { handover: Handover::Crossfade(_, fade), .. }) if pos + media(*fade) >= duration
+            );
+
+        if ended || fading {
+            if let Some(key_thread) = key_thread {
+                *key_state.lock().unwrap() = true;
+                let _ = key_thread.await;
+                cleanup_and_exit(&pb, now.metadata.clone(), &now.filename);
+            }
+            save_position(now, None, settings);
+            if sleep_at_end {
+                return TrackEnd::Stop(Command::Quit);
+            }
+
+            let Some(upcoming) = upcoming else {
+                return TrackEnd::Stop(Command::Ended);
+            };
+
+            if let Handover::Crossfade(player, fade) = upcoming.handover {
+                // The decoder may run out before the tagged duration says it should
+                let fade = if ended { Duration::ZERO } else { fade };
+                music_play.lock().unwrap().crossfade_to(player, fade);
+            }
+            queue.lock().unwrap().apply(Command::Ended);
+            return TrackEnd::Next(Box::new(upcoming.now));
         }
 
         sleep(Duration::from_millis(TICK_INTERVAL_MS)).await;
@@ -136,13 +459,42 @@ async fn really_play(
 
             if tick_count >= TICKS_PER_SECOND {
                 tick_count = 0;
-                let current_secs = music_play.lock().unwrap().get_pos().as_secs();
-                update_progress(&pb, current_secs, duration_secs);
+                let play = music_play.lock().unwrap();
+                let timer = settings.sleep_timer.lock().unwrap();
+                update_progress(
+                    &pb,
+                    play.get_pos().as_secs(),
+                    duration_secs,
+                    play.ab_loop(),
+                    &timer,
+                );
             }
         }
     }
 }
 
+/// Moves on to the track appended behind the current one like [`MusicPlay::skip_one`],
+/// polling for the sink to let go of it without holding on to the player
+async fn skip_one(music_play: &Mutex<MusicPlay>) {
+    let left = when_silent(music_play, |play| play.begin_skip()).await;
+    while !music_play.lock().unwrap().skipped(left) {
+        sleep(SKIP_POLL_INTERVAL).await;
+    }
+}
+
+/// Records where the track was left, or that it should start over with `None`
+fn save_position(now: &NowPlaying, left_at: Option<Duration>, settings: &Settings) {
+    let mut positions = settings.positions.lock().unwrap();
+    let location = &now.track.location;
+    let saved = match left_at {
+        Some(pos) => positions.remember(location, pos, now.metadata.duration()),
+        None => positions.forget(location),
+    };
+    if let Err(e) = saved {
+        warn!("Failed to save the position in {}: {}", now.filename, e);
+    }
+}
+
 fn create_progress_bar(duration: u64) -> ProgressBar {
     let pb = ProgressBar::new(duration);
     pb.set_style(
@@ -160,12 +512,35 @@ fn create_progress_bar(duration: u64) -> ProgressBar {
     pb
 }
 
-fn update_progress(pb: &ProgressBar, current: u64, total: u64) {
+fn update_progress(
+    pb: &ProgressBar,
+    current: u64,
+    total: u64,
+    ab_loop: Option<AbLoop>,
+    sleep_timer: &SleepTimer,
+) {
+    let whole_secs = |pos: Duration| format_duration(Duration::from_secs(pos.as_secs()));
+    let looping = match ab_loop {
+        Some(AbLoop {
+            start,
+            end: Some(end),
+        }) => format!("  [A-B {} - {}]", whole_secs(start), whole_secs(end)),
+        Some(AbLoop { start, end: None }) => format!("  [A {} -]", whole_secs(start)),
+        None => String::new(),
+    };
+    let sleeping = match sleep_timer.remaining() {
+        Some(left) => format!("  [sleep in {}]", whole_secs(left)),
+        None if sleep_timer.ends_with_track() => String::from("  [sleep after this track]"),
+        None => String::new(),
+    };
+
     pb.set_position(current);
     pb.set_message(format!(
-        "{} / {}",
+        "{} / {}{}{}",
         format_duration(Duration::from_secs(current)),
-        format_duration(Duration::from_secs(total))
+        format_duration(Duration::from_secs(total)),
+        looping,
+        sleeping
     ));
 }
 
@@ -0,0 +1,353 @@
+use crate::biquad::Biquad;
+use rodio::Source;
+use rodio::source::SeekError;
+use std::f64::consts::PI;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Butterworth response for shelves, a moderately wide bell for peaks
+const DEFAULT_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+/// New bands are crossfaded in over this many milliseconds, since filters started mid-signal
+/// ring before they settle
+const CROSSFADE_MS: u32 = 20;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BandKind {
+    /// Boosts or cuts around the frequency
+    Peak,
+    /// Boosts or cuts everything below the frequency
+    LowShelf,
+    /// Boosts or cuts everything above the frequency
+    HighShelf,
+}
+
+/// One filter of the equalizer
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Band {
+    pub kind: BandKind,
+    /// Center or corner frequency in Hz
+    pub freq: f64,
+    pub gain_db: f64,
+    pub q: f64,
+}
+
+impl Band {
+    /// Coefficients from the Audio EQ Cookbook
+    fn filter(&self, sample_rate: u32) -> Biquad {
+        let a = 10f64.powf(self.gain_db / 40.0);
+        // Frequencies at or above Nyquist would make the filter unstable
+        let freq = self.freq.min(sample_rate as f64 * 0.49);
+        let w0 = 2.0 * PI * freq / sample_rate as f64;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * self.q);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b, a) = match self.kind {
+            BandKind::Peak => (
+                [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+                [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+            ),
+            BandKind::LowShelf => (
+                [
+                    a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                ],
+                [
+                    (a + 1.0) + (a - 1.0) * cos + shelf,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - shelf,
+                ],
+            ),
+            BandKind::HighShelf => (
+                [
+                    a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                ],
+                [
+                    (a + 1.0) - (a - 1.0) * cos + shelf,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - shelf,
+                ],
+            ),
+        };
+
+        Biquad::new(
+            [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            [a[1] / a[0], a[2] / a[0]],
+        )
+    }
+}
+
+/// Reads `[peak:|low:|high:]FREQ:GAIN[:Q]`, e.g. `low:105:4.5` or `2700:-3:2`
+impl FromStr for Band {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = s.trim().split(':').collect();
+
+        let kind = match parts.first().copied() {
+            Some("peak") => Some(BandKind::Peak),
+            Some("low") => Some(BandKind::LowShelf),
+            Some("high") => Some(BandKind::HighShelf),
+            _ => None,
+        };
+        if kind.is_some() {
+            parts.remove(0);
+        }
+
+        let number = |part: &str, what: &str| {
+            part.parse::<f64>()
+                .map_err(|_| format!("invalid {} {:?} in band {:?}", what, part, s))
+        };
+
+        let (freq, gain_db, q) = match parts[..] {
+            [freq, gain] => (number(freq, "frequency")?, number(gain, "gain")?, DEFAULT_Q),
+            [freq, gain, q] => (
+                number(freq, "frequency")?,
+                number(gain, "gain")?,
+                number(q, "Q")?,
+            ),
+            _ => {
+                return Err(format!(
+                    "expected [peak:|low:|high:]FREQ:GAIN[:Q], got {:?}",
+                    s
+                ));
+            }
+        };
+
+        if freq <= 0.0 || q <= 0.0 {
+            return Err(format!("frequency and Q must be positive in band {:?}", s));
+        }
+
+        Ok(Band {
+            kind: kind.unwrap_or(BandKind::Peak),
+            freq,
+            gain_db,
+            q,
+        })
+    }
+}
+
+struct Shared {
+    /// Bumped on every change so sources notice without locking each frame
+    generation: AtomicU64,
+    bands: Mutex<Arc<Vec<Band>>>,
+}
+
+/// Bands shared by the [`Equalizer`]s it applies, which crossfade to new ones as they are set
+#[derive(Clone)]
+pub struct EqControl {
+    shared: Arc<Shared>,
+}
+
+impl EqControl {
+    pub fn new(bands: Vec<Band>) -> Self {
+        EqControl {
+            shared: Arc::new(Shared {
+                generation: AtomicU64::new(0),
+                bands: Mutex::new(Arc::new(bands)),
+            }),
+        }
+    }
+
+    pub fn set_bands(&self, bands: Vec<Band>) {
+        *self.shared.bands.lock().unwrap() = Arc::new(bands);
+        self.shared.generation.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn bands(&self) -> Arc<Vec<Band>> {
+        Arc::clone(&self.shared.bands.lock().unwrap())
+    }
+
+    /// Wraps `inner` in an equalizer that follows this control
+    pub fn apply<S: Source>(&self, inner: S) -> Equalizer<S> {
+        Equalizer {
+            inner,
+            control: self.clone(),
+            generation: None,
+            format: (0, 0),
+            filters: Vec::new(),
+            previous: Vec::new(),
+            fade_left: 0,
+            fade_len: 0,
+            channel: 0,
+        }
+    }
+}
+
+impl Default for EqControl {
+    fn default() -> Self {
+        EqControl::new(Vec::new())
+    }
+}
+
+/// Runs every channel through a chain of peaking and shelving filters
+pub struct Equalizer<S> {
+    inner: S,
+    control: EqControl,
+    generation: Option<u64>,
+    /// Channels and sample rate the filters were built for
+    format: (u16, u32),
+    /// One chain of filters per channel
+    filters: Vec<Vec<Biquad>>,
+    /// The chains used before the bands last changed, still running while they fade out
+    previous: Vec<Vec<Biquad>>,
+    /// Frames left of the crossfade from `previous` to `filters`, out of `fade_len`
+    fade_left: usize,
+    fade_len: usize,
+    channel: u16,
+}
+
+impl<S: Source> Equalizer<S> {
+    fn rebuild(&mut self, generation: u64, format: (u16, u32)) {
+        let (channels, sample_rate) = format;
+        let bands = self.control.bands();
+        let chain: Vec<Biquad> = bands.iter().map(|band| band.filter(sample_rate)).collect();
+        let chains = vec![chain; channels as usize];
+
+        // Only a change of bands in the middle of the signal needs smoothing over
+        if self.format == format && self.generation.is_some() {
+            self.previous = std::mem::replace(&mut self.filters, chains);
+            self.fade_len = (sample_rate * CROSSFADE_MS / 1000).max(1) as usize;
+            self.fade_left = self.fade_len;
+        } else {
+            self.filters = chains;
+            self.previous.clear();
+            self.fade_left = 0;
+        }
+        self.generation = Some(generation);
+        self.format = format;
+    }
+}
+
+fn filter(chain: &mut [Biquad], sample: f64) -> f64 {
+    chain.iter_mut().fold(sample, |x, filter| filter.process(x))
+}
+
+impl<S: Source> Iterator for Equalizer<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Filters are only swapped on a frame boundary, and new bands wait for a running
+        // crossfade to finish
+        if self.channel == 0 {
+            let generation = self.control.shared.generation.load(Ordering::Acquire);
+            let format = (self.inner.channels(), self.inner.sample_rate());
+            if self.format != format || (self.generation != Some(generation) && self.fade_left == 0)
+            {
+                self.rebuild(generation, format);
+            }
+        }
+
+        let sample = self.inner.next()?;
+        let channel = self.channel as usize;
+        self.channel = (self.channel + 1) % self.format.0.max(1);
+
+        if self.fade_left == 0 {
+            if self.filters[channel].is_empty() {
+                return Some(sample);
+            }
+            return Some(filter(&mut self.filters[channel], sample as f64) as f32);
+        }
+
+        let new = filter(&mut self.filters[channel], sample as f64);
+        let old = filter(&mut self.previous[channel], sample as f64);
+        let progress = 1.0 - self.fade_left as f64 / self.fade_len as f64;
+        if self.channel == 0 {
+            self.fade_left -= 1;
+            if self.fade_left == 0 {
+                self.previous.clear();
+            }
+        }
+        Some((old + (new - old) * progress) as f32)
+    }
+}
+
+impl<S: Source> Source for Equalizer<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.inner.current_span_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn band(s: &str) -> Band {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn kinds_by_prefix() {
+        assert_eq!(band("peak:1000:3:2").kind, BandKind::Peak);
+        assert_eq!(band("low:105:4.5:0.7").kind, BandKind::LowShelf);
+        assert_eq!(band("high:8000:-2:1").kind, BandKind::HighShelf);
+        assert_eq!(band("2700:-3:2").kind, BandKind::Peak);
+        assert_eq!(
+            band(" low:105:4.5:0.7 "),
+            Band {
+                kind: BandKind::LowShelf,
+                freq: 105.0,
+                gain_db: 4.5,
+                q: 0.7,
+            }
+        );
+    }
+
+    #[test]
+    fn q_may_be_left_out() {
+        assert_eq!(band("low:105:4.5").q, DEFAULT_Q);
+        assert_eq!(band("2700:-3").q, DEFAULT_Q);
+        assert_eq!(band("2700:-3").gain_db, -3.0);
+    }
+
+    #[test]
+    fn bad_numbers() {
+        for s in [
+            "loud:3",
+            "low:bass:3",
+            "1000:up",
+            "1000:3:wide",
+            "0:3",
+            "-100:3",
+            "1000:3:0",
+            "1000:3:-1",
+        ] {
+            assert!(s.parse::<Band>().is_err(), "{} parsed", s);
+        }
+    }
+
+    #[test]
+    fn wrong_number_of_parts() {
+        for s in [
+            "",
+            "1000",
+            "low:1000",
+            "1000:3:2:1",
+            "peak:1000:3:2:1",
+            "treble:1000:3",
+        ] {
+            assert!(s.parse::<Band>().is_err(), "{} parsed", s);
+        }
+    }
+}
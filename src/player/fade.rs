@@ -0,0 +1,225 @@
+use rodio::Source;
+use rodio::source::SeekError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+struct Ramp {
+    target: f32,
+    over: Duration,
+    stop_at_end: bool,
+}
+
+struct Shared {
+    pending: AtomicBool,
+    ramp: Mutex<Option<Ramp>>,
+}
+
+/// Handle used to ramp the gain of a [`Fade`] source from another thread
+#[derive(Clone)]
+pub struct FadeControl {
+    shared: Arc<Shared>,
+}
+
+impl FadeControl {
+    fn new() -> Self {
+        FadeControl {
+            shared: Arc::new(Shared {
+                pending: AtomicBool::new(false),
+                ramp: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Linearly moves the gain to `target` over `over`
+    pub fn ramp_to(&self, target: f32, over: Duration) {
+        self.request(Ramp {
+            target,
+            over,
+            stop_at_end: false,
+        });
+    }
+
+    /// Ramps the gain down to silence and then ends the source
+    pub fn fade_out(&self, over: Duration) {
+        self.request(Ramp {
+            target: 0.0,
+            over,
+            stop_at_end: true,
+        });
+    }
+
+    fn request(&self, ramp: Ramp) {
+        *self.shared.ramp.lock().unwrap() = Some(ramp);
+        self.shared.pending.store(true, Ordering::Release);
+    }
+}
+
+/// Applies a gain that can be ramped sample by sample, so volume changes never click
+pub struct Fade<S> {
+    inner: S,
+    control: FadeControl,
+    gain: f32,
+    target: f32,
+    step: f32,
+    remaining: u64,
+    stop_at_end: bool,
+    stopped: bool,
+    channel: u16,
+}
+
+impl<S: Source> Fade<S> {
+    /// Wraps `inner` starting at the given gain
+    pub fn new(inner: S, gain: f32) -> (Self, FadeControl) {
+        let control = FadeControl::new();
+        let fade = Fade {
+            inner,
+            control: control.clone(),
+            gain,
+            target: gain,
+            step: 0.0,
+            remaining: 0,
+            stop_at_end: false,
+            stopped: false,
+            channel: 0,
+        };
+        (fade, control)
+    }
+
+    fn apply_pending(&mut self) {
+        let Some(ramp) = self.control.shared.ramp.lock().unwrap().take() else {
+            return;
+        };
+
+        // Whole frames only, so a fade-out never ends between two channels
+        let frames = (ramp.over.as_secs_f32() * self.inner.sample_rate() as f32) as u64;
+        let samples = frames * self.inner.channels() as u64;
+
+        self.stop_at_end = ramp.stop_at_end;
+        self.target = ramp.target;
+        if samples == 0 {
+            self.gain = ramp.target;
+            self.remaining = 0;
+            self.stopped = self.stop_at_end;
+        } else {
+            self.step = (ramp.target - self.gain) / samples as f32;
+            self.remaining = samples;
+        }
+    }
+}
+
+impl<S: Source> Iterator for Fade<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Ramps only start on a frame boundary
+        if self.channel == 0 && self.control.shared.pending.swap(false, Ordering::Acquire) {
+            self.apply_pending();
+        }
+
+        if self.stopped {
+            return None;
+        }
+
+        let sample = self.inner.next()?;
+        self.channel = (self.channel + 1) % self.inner.channels().max(1);
+
+        if self.remaining > 0 {
+            self.gain += self.step;
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                self.gain = self.target;
+                self.stopped = self.stop_at_end;
+            }
+        }
+
+        Some(sample * self.gain)
+    }
+}
+
+impl<S: Source> Source for Fade<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.inner.current_span_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    /// A second of stereo at full scale, at 1 kHz so a millisecond is a frame
+    fn ones(gain: f32) -> (Fade<SamplesBuffer>, FadeControl) {
+        Fade::new(SamplesBuffer::new(2, 1000, vec![1.0; 2000]), gain)
+    }
+
+    #[test]
+    fn ramp_reaches_its_target() {
+        let (mut fade, control) = ones(1.0);
+        control.ramp_to(0.0, Duration::from_millis(100));
+        let out: Vec<f32> = fade.by_ref().take(400).collect();
+
+        assert!(out[0] < 1.0 && out[0] > 0.99);
+        assert!(out.windows(2).take(199).all(|w| w[1] < w[0]));
+        assert_eq!(out[199], 0.0);
+        assert!(out[200..].iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn ramp_up_from_silence() {
+        let (mut fade, control) = ones(0.0);
+        assert_eq!(fade.next(), Some(0.0));
+        fade.next();
+        control.ramp_to(0.5, Duration::from_millis(10));
+        let out: Vec<f32> = fade.by_ref().take(40).collect();
+        assert!(out[0] > 0.0 && out[0] < 0.05);
+        assert_eq!(out[19], 0.5);
+        assert!(out[20..].iter().all(|&sample| sample == 0.5));
+    }
+
+    #[test]
+    fn zero_duration_is_instant() {
+        let (mut fade, control) = ones(1.0);
+        control.ramp_to(0.25, Duration::ZERO);
+        assert!(fade.by_ref().take(10).all(|sample| sample == 0.25));
+    }
+
+    #[test]
+    fn ramps_start_between_frames() {
+        let (mut fade, control) = ones(1.0);
+        assert_eq!(fade.next(), Some(1.0));
+        control.ramp_to(0.0, Duration::ZERO);
+        // The right sample of the frame already begun keeps the old gain
+        assert_eq!(fade.next(), Some(1.0));
+        assert_eq!(fade.next(), Some(0.0));
+    }
+
+    #[test]
+    fn fade_out_ends_the_source() {
+        let (fade, control) = ones(1.0);
+        control.fade_out(Duration::from_millis(50));
+        let out: Vec<f32> = fade.collect();
+        assert_eq!(out.len(), 100);
+        assert_eq!(out.last(), Some(&0.0));
+
+        let (mut fade, control) = ones(1.0);
+        control.fade_out(Duration::ZERO);
+        assert_eq!(fade.next(), None);
+    }
+}
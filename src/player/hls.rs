@@ -0,0 +1,793 @@
+use crate::player::url::{HttpClient, StreamError, fetch};
+use bytes::Bytes;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use url::Url;
+
+/// Segments waiting to be decoded; each holds several seconds of audio already
+const QUEUED_SEGMENTS: usize = 3;
+/// How many segments from the end a live stream starts, as RFC 8216 asks of clients
+const LIVE_EDGE_SEGMENTS: usize = 3;
+/// Shortest wait between reloads of a live playlist, whatever target duration it gives
+const MIN_RELOAD_WAIT: Duration = Duration::from_secs(1);
+
+const TS_PACKET: usize = 188;
+const TS_SYNC: u8 = 0x47;
+const STREAM_TYPE_MPEG1_AUDIO: u8 = 0x03;
+const STREAM_TYPE_MPEG2_AUDIO: u8 = 0x04;
+const STREAM_TYPE_ADTS: u8 = 0x0f;
+
+/// Whether a response for `url` is an HLS playlist rather than audio, going by its
+/// content type or, for servers that do not set one properly, its name
+pub(crate) fn is_playlist(url: &str, content_type: Option<&str>) -> bool {
+    let by_type = content_type.is_some_and(|content_type| {
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        ["application/vnd.apple.mpegurl", "application/x-mpegurl"]
+            .iter()
+            .any(|hls| mime.eq_ignore_ascii_case(hls))
+    });
+    let by_name = Url::parse(url).is_ok_and(|url| url.path().ends_with(".m3u8"));
+    by_type || by_name
+}
+
+/// Whether `playlist` is an HLS playlist rather than a plain list of tracks
+pub fn is_hls(playlist: &str) -> bool {
+    playlist.trim_start().starts_with("#EXTM3U") && playlist.contains("#EXT-X-")
+}
+
+/// Whether a playlist that starts with `head` is HLS, going by its complete lines: HLS tags
+/// come before the first URI, where a plain list names a track first. `None` while those
+/// lines tell neither.
+pub fn head_is_hls(head: &str) -> Option<bool> {
+    let complete = &head[..head.rfind('\n').map_or(0, |end| end + 1)];
+    let mut lines = complete
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty());
+    if !lines.next()?.starts_with("#EXTM3U") {
+        return Some(false);
+    }
+    for line in lines {
+        if line.starts_with("#EXT-X-") {
+            return Some(true);
+        }
+        if !line.starts_with('#') {
+            return Some(false);
+        }
+    }
+    None
+}
+
+struct Variant {
+    uri: Url,
+    bandwidth: u64,
+    /// Named group of the audio renditions that go with this variant
+    audio: Option<String>,
+    audio_only: bool,
+}
+
+struct Rendition {
+    uri: Url,
+    group: String,
+    default: bool,
+}
+
+#[derive(Clone)]
+struct Segment {
+    sequence: u64,
+    uri: Url,
+    /// Initialization section that has to come before the segment, for fMP4
+    map: Option<Url>,
+}
+
+struct MediaPlaylist {
+    target_duration: Duration,
+    segments: Vec<Segment>,
+    /// No segments will be added, so the stream ends after the last one
+    ended: bool,
+}
+
+impl MediaPlaylist {
+    /// Sequence number of the segment to play first: live streams start near the newest
+    /// segments rather than ones about to expire
+    fn start(&self) -> u64 {
+        let start = if self.ended {
+            0
+        } else {
+            self.segments.len().saturating_sub(LIVE_EDGE_SEGMENTS)
+        };
+        self.segments.get(start).map_or(0, |s| s.sequence)
+    }
+}
+
+enum Playlist {
+    Master {
+        variants: Vec<Variant>,
+        renditions: Vec<Rendition>,
+    },
+    Media(MediaPlaylist),
+}
+
+/// Splits an attribute list such as `BANDWIDTH=128000,CODECS="mp4a.40.2"` into names and
+/// values, with quotes taken off
+fn attributes(list: &str) -> Vec<(&str, &str)> {
+    let mut attributes = Vec::new();
+    let mut rest = list.trim();
+    while let Some((name, after)) = rest.split_once('=') {
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let after = quoted.get(end + 1..).unwrap_or("");
+                (&quoted[..end], after.split_once(',').map_or("", |(_, r)| r))
+            }
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        attributes.push((name.trim(), value));
+        rest = after.trim_start();
+    }
+    attributes
+}
+
+fn attribute<'a>(attributes: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| *value)
+}
+
+fn is_video_codec(codec: &str) -> bool {
+    ["avc1", "avc3", "hvc1", "hev1", "vp09", "av01"]
+        .iter()
+        .any(|video| codec.trim().starts_with(video))
+}
+
+/// Reads a master or media playlist, resolving its URIs against `base`
+fn parse(text: &str, base: &Url) -> Result<Playlist, StreamError> {
+    let mut variants = Vec::new();
+    let mut renditions = Vec::new();
+    let mut segments = Vec::new();
+    let mut target_duration = Duration::from_secs(10);
+    let mut media_sequence = 0;
+    let mut ended = false;
+    let mut map = None;
+    let mut stream_inf: Option<Vec<(&str, &str)>> = None;
+
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(tag) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            stream_inf = Some(attributes(tag));
+        } else if let Some(tag) = line.strip_prefix("#EXT-X-MEDIA:") {
+            let attributes = attributes(tag);
+            if attribute(&attributes, "TYPE") == Some("AUDIO")
+                && let Some(uri) = attribute(&attributes, "URI")
+            {
+                renditions.push(Rendition {
+                    uri: base.join(uri)?,
+                    group: attribute(&attributes, "GROUP-ID").unwrap_or("").to_string(),
+                    default: attribute(&attributes, "DEFAULT") == Some("YES"),
+                });
+            }
+        } else if let Some(tag) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            // Whole seconds by the spec, though some servers write fractions
+            target_duration = Duration::try_from_secs_f64(tag.trim().parse()?)?;
+        } else if let Some(tag) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            media_sequence = tag.trim().parse()?;
+        } else if line == "#EXT-X-ENDLIST" {
+            ended = true;
+        } else if let Some(tag) = line.strip_prefix("#EXT-X-KEY:") {
+            if attribute(&attributes(tag), "METHOD").is_some_and(|method| method != "NONE") {
+                return Err("Encrypted HLS streams are not supported".into());
+            }
+        } else if let Some(tag) = line.strip_prefix("#EXT-X-MAP:") {
+            let attributes = attributes(tag);
+            if attribute(&attributes, "BYTERANGE").is_some() {
+                return Err("HLS byte ranges are not supported".into());
+            }
+            let uri = attribute(&attributes, "URI").ok_or("EXT-X-MAP without a URI")?;
+            map = Some(base.join(uri)?);
+        } else if line.starts_with("#EXT-X-BYTERANGE") {
+            return Err("HLS byte ranges are not supported".into());
+        } else if !line.starts_with('#') {
+            let uri = base.join(line)?;
+            match stream_inf.take() {
+                Some(attributes) => {
+                    let codecs = attribute(&attributes, "CODECS");
+                    variants.push(Variant {
+                        uri,
+                        bandwidth: attribute(&attributes, "BANDWIDTH")
+                            .and_then(|bandwidth| bandwidth.parse().ok())
+                            .unwrap_or(0),
+                        audio: attribute(&attributes, "AUDIO").map(String::from),
+                        audio_only: codecs
+                            .is_some_and(|codecs| !codecs.split(',').any(is_video_codec)),
+                    });
+                }
+                None => segments.push(Segment {
+                    sequence: media_sequence + segments.len() as u64,
+                    uri,
+                    map: map.clone(),
+                }),
+            }
+        }
+    }
+
+    if !variants.is_empty() {
+        return Ok(Playlist::Master {
+            variants,
+            renditions,
+        });
+    }
+    Ok(Playlist::Media(MediaPlaylist {
+        target_duration,
+        segments,
+        ended,
+    }))
+}
+
+/// Picks the media playlist to play from a master playlist: the audio rendition that goes
+/// with the leanest variant when audio comes on its own, otherwise the richest audio-only
+/// variant, otherwise the leanest variant with the audio muxed in
+fn pick_audio(variants: &[Variant], renditions: &[Rendition]) -> Option<Url> {
+    let leanest = variants.iter().min_by_key(|variant| variant.bandwidth)?;
+    let group = leanest.audio.as_deref();
+    let in_group: Vec<&Rendition> = renditions
+        .iter()
+        .filter(|rendition| group.is_none_or(|group| rendition.group == group))
+        .collect();
+    if let Some(rendition) = in_group
+        .iter()
+        .find(|rendition| rendition.default)
+        .or(in_group.first())
+    {
+        return Some(rendition.uri.clone());
+    }
+
+    let audio_only = variants
+        .iter()
+        .filter(|variant| variant.audio_only)
+        .max_by_key(|variant| variant.bandwidth);
+    Some(audio_only.unwrap_or(leanest).uri.clone())
+}
+
+/// How the audio is packed into segments
+enum Packing {
+    Ts(TsDemuxer),
+    /// Fragmented MP4, played as the initialization section followed by every segment
+    Fmp4,
+    /// Raw ADTS or MPEG audio, each segment usually led by an ID3 tag with its timestamp
+    Raw,
+}
+
+/// Follows a media playlist, downloading its segments in order and turning them into one
+/// continuous stream for symphonia
+struct Segments {
+    client: HttpClient,
+    url: Url,
+    playlist: MediaPlaylist,
+    /// Sequence number of the next segment to download
+    next: u64,
+    /// Initialization section sent last
+    map: Option<Url>,
+    packing: Option<Packing>,
+    downloaded: Arc<Mutex<u64>>,
+}
+
+impl Segments {
+    async fn get(&self, url: &Url) -> Result<Bytes, StreamError> {
+        let (body, _) = fetch(&self.client, url.as_str()).await?;
+        *self.downloaded.lock().unwrap() += body.len() as u64;
+        Ok(body)
+    }
+
+    /// Downloads the next segment, waiting for a live playlist to grow when it has been
+    /// played through. Returns `None` once the last segment of a finished stream is done.
+    async fn next(&mut self) -> Result<Option<Bytes>, StreamError> {
+        loop {
+            let next = self.next;
+            if let Some(segment) = self
+                .playlist
+                .segments
+                .iter()
+                .find(|segment| segment.sequence >= next)
+                .cloned()
+            {
+                self.next = segment.sequence + 1;
+                let mut data = Vec::new();
+                if segment.map != self.map {
+                    if let Some(map) = &segment.map {
+                        data.extend_from_slice(&self.get(map).await?);
+                    }
+                    self.map = segment.map.clone();
+                }
+                let body = self.get(&segment.uri).await?;
+                self.unpack(&body, &mut data);
+                return Ok(Some(Bytes::from(data)));
+            }
+
+            if self.playlist.ended {
+                return Ok(None);
+            }
+            self.reload().await?;
+        }
+    }
+
+    /// Waits as RFC 8216 asks and fetches the live playlist again, waiting only half as long
+    /// once it comes back unchanged
+    async fn reload(&mut self) -> Result<(), StreamError> {
+        let mut wait = self.playlist.target_duration.max(MIN_RELOAD_WAIT);
+        loop {
+            tokio::time::sleep(wait).await;
+            let (text, _) = fetch(&self.client, self.url.as_str()).await?;
+            let Playlist::Media(playlist) = parse(&String::from_utf8_lossy(&text), &self.url)?
+            else {
+                return Err("Live playlist turned into a master playlist".into());
+            };
+            let grown = playlist
+                .segments
+                .last()
+                .is_some_and(|segment| segment.sequence >= self.next);
+            self.playlist = playlist;
+            if grown || self.playlist.ended {
+                return Ok(());
+            }
+            wait = (self.playlist.target_duration / 2).max(MIN_RELOAD_WAIT);
+        }
+    }
+
+    /// Appends the audio in `body` to `data`, working out how segments are packed from the
+    /// first one
+    fn unpack(&mut self, body: &[u8], data: &mut Vec<u8>) {
+        let packing = self.packing.get_or_insert_with(|| {
+            if self.map.is_some() {
+                Packing::Fmp4
+            } else if body.first() == Some(&TS_SYNC)
+                && body.get(TS_PACKET).is_none_or(|&byte| byte == TS_SYNC)
+            {
+                Packing::Ts(TsDemuxer::default())
+            } else {
+                Packing::Raw
+            }
+        });
+        match packing {
+            Packing::Ts(demuxer) => demuxer.push(body, data),
+            Packing::Fmp4 => data.extend_from_slice(body),
+            Packing::Raw => data.extend_from_slice(skip_id3(body)),
+        }
+    }
+
+    /// Extension symphonia should probe the unpacked stream as, starting with `first`
+    fn extension(&self, first: &[u8]) -> Result<&'static str, StreamError> {
+        match &self.packing {
+            Some(Packing::Fmp4) => Ok("mp4"),
+            Some(Packing::Ts(demuxer)) => match demuxer.audio {
+                Some((_, STREAM_TYPE_ADTS)) => Ok("aac"),
+                Some((_, STREAM_TYPE_MPEG1_AUDIO | STREAM_TYPE_MPEG2_AUDIO)) => Ok("mp3"),
+                _ => Err("No supported audio in the MPEG-TS segments".into()),
+            },
+            // ADTS frames start with 12 set bits and a zero layer, MPEG audio frames do not
+            _ if first.len() >= 2 && first[0] == 0xff && first[1] & 0xf6 == 0xf0 => Ok("aac"),
+            _ => Ok("mp3"),
+        }
+    }
+}
+
+/// Leaves out the ID3 tags in front of `segment`
+fn skip_id3(mut segment: &[u8]) -> &[u8] {
+    while segment.len() >= 10 && segment.starts_with(b"ID3") {
+        // Sizes are stored 7 bits to a byte, leaving out the header and any footer
+        let size = segment[6..10]
+            .iter()
+            .fold(0usize, |size, &byte| (size << 7) | (byte & 0x7f) as usize);
+        let footer = if segment[5] & 0x10 != 0 { 10 } else { 0 };
+        segment = segment.get(10 + size + footer..).unwrap_or(&[]);
+    }
+    segment
+}
+
+/// Pulls the first audio stream out of MPEG transport stream segments as the bare ADTS or
+/// MPEG audio it carries
+#[derive(Default)]
+struct TsDemuxer {
+    pmt_pid: Option<u16>,
+    /// PID and stream type of the audio
+    audio: Option<(u16, u8)>,
+}
+
+impl TsDemuxer {
+    fn push(&mut self, segment: &[u8], out: &mut Vec<u8>) {
+        for packet in segment.chunks_exact(TS_PACKET) {
+            if packet[0] != TS_SYNC {
+                continue;
+            }
+            let pid = u16::from_be_bytes([packet[1] & 0x1f, packet[2]]);
+            let unit_start = packet[1] & 0x40 != 0;
+            let control = (packet[3] >> 4) & 0x3;
+            if control & 0x1 == 0 {
+                continue;
+            }
+            // An adaptation field sits between the header and the payload
+            let offset = if control & 0x2 != 0 {
+                5 + packet[4] as usize
+            } else {
+                4
+            };
+            let Some(payload) = packet.get(offset..) else {
+                continue;
+            };
+
+            if pid == 0 && unit_start {
+                self.pmt_pid = program_map_pid(payload).or(self.pmt_pid);
+            } else if Some(pid) == self.pmt_pid && unit_start {
+                self.audio = self.audio.or_else(|| audio_stream(payload));
+            } else if self.audio.is_some_and(|(audio, _)| audio == pid) {
+                let data = if unit_start {
+                    pes_payload(payload)
+                } else {
+                    Some(payload)
+                };
+                out.extend_from_slice(data.unwrap_or(&[]));
+            }
+        }
+    }
+}
+
+/// The PSI section starting in `payload`, from its table ID up to its CRC
+fn section(payload: &[u8]) -> Option<&[u8]> {
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+    let len = u16::from_be_bytes([section.get(1)? & 0x0f, *section.get(2)?]) as usize;
+    section.get(..(3 + len).checked_sub(4)?)
+}
+
+/// PID of the first program's map in a program association table
+fn program_map_pid(payload: &[u8]) -> Option<u16> {
+    section(payload)?
+        .get(8..)?
+        .chunks_exact(4)
+        // Program 0 points at the network information table instead
+        .find(|program| program[0] != 0 || program[1] != 0)
+        .map(|program| u16::from_be_bytes([program[2] & 0x1f, program[3]]))
+}
+
+/// PID and stream type of the first audio stream symphonia can decode in a program map table
+fn audio_stream(payload: &[u8]) -> Option<(u16, u8)> {
+    let section = section(payload)?;
+    let info_len = u16::from_be_bytes([section.get(10)? & 0x0f, *section.get(11)?]) as usize;
+    let mut streams = section.get(12 + info_len..)?;
+    while streams.len() >= 5 {
+        let stream_type = streams[0];
+        let pid = u16::from_be_bytes([streams[1] & 0x1f, streams[2]]);
+        if matches!(
+            stream_type,
+            STREAM_TYPE_ADTS | STREAM_TYPE_MPEG1_AUDIO | STREAM_TYPE_MPEG2_AUDIO
+        ) {
+            return Some((pid, stream_type));
+        }
+        let es_info_len = u16::from_be_bytes([streams[3] & 0x0f, streams[4]]) as usize;
+        streams = streams.get(5 + es_info_len..)?;
+    }
+    None
+}
+
+/// The data of a PES packet starting in `payload`, after its header
+fn pes_payload(payload: &[u8]) -> Option<&[u8]> {
+    if payload.get(..3)? != [0, 0, 1] {
+        return None;
+    }
+    let header_len = *payload.get(8)? as usize;
+    payload.get(9 + header_len..)
+}
+
+/// Starts playing the HLS playlist `text` that came from `url`: picks the audio out of a
+/// master playlist, downloads the first segment and keeps downloading the rest in the
+/// background, refreshing live playlists. Returns the stream of audio along with the
+/// extension to probe it as.
+pub(crate) async fn open(
+    client: HttpClient,
+    url: &str,
+    text: &str,
+    downloaded: Arc<Mutex<u64>>,
+    error: Arc<Mutex<Option<String>>>,
+) -> Result<(mpsc::Receiver<Bytes>, &'static str), StreamError> {
+    let mut url = Url::parse(url)?;
+    let playlist = match parse(text, &url)? {
+        Playlist::Master {
+            variants,
+            renditions,
+        } => {
+            let audio = pick_audio(&variants, &renditions).ok_or("No playable HLS variant")?;
+            let (text, from) = fetch(&client, audio.as_str()).await?;
+            *downloaded.lock().unwrap() += text.len() as u64;
+            url = Url::parse(&from)?;
+            match parse(&String::from_utf8_lossy(&text), &url)? {
+                Playlist::Media(playlist) => playlist,
+                Playlist::Master { .. } => return Err("HLS variant is a master playlist".into()),
+            }
+        }
+        Playlist::Media(playlist) => playlist,
+    };
+
+    let next = playlist.start();
+    let mut segments = Segments {
+        client,
+        url,
+        playlist,
+        next,
+        map: None,
+        packing: None,
+        downloaded,
+    };
+    let first = segments
+        .next()
+        .await?
+        .ok_or("HLS playlist has no segments")?;
+    let extension = segments.extension(&first)?;
+
+    let (tx, rx) = mpsc::channel::<Bytes>(QUEUED_SEGMENTS);
+    tx.send(first).await?;
+    tokio::spawn(async move {
+        loop {
+            match segments.next().await {
+                Ok(Some(data)) => {
+                    if tx.send(data).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    *error.lock().unwrap() = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok((rx, extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://example.com/live/master.m3u8").unwrap()
+    }
+
+    fn master(text: &str) -> (Vec<Variant>, Vec<Rendition>) {
+        match parse(text, &base()).unwrap() {
+            Playlist::Master {
+                variants,
+                renditions,
+            } => (variants, renditions),
+            Playlist::Media(_) => panic!("read as a media playlist"),
+        }
+    }
+
+    fn media(text: &str) -> MediaPlaylist {
+        match parse(text, &base()).unwrap() {
+            Playlist::Media(playlist) => playlist,
+            Playlist::Master { .. } => panic!("read as a master playlist"),
+        }
+    }
+
+    #[test]
+    fn quoted_values_keep_their_commas() {
+        assert_eq!(
+            attributes(r#"BANDWIDTH=128000,CODECS="mp4a.40.2,avc1.4d401e",AUDIO="aud""#),
+            [
+                ("BANDWIDTH", "128000"),
+                ("CODECS", "mp4a.40.2,avc1.4d401e"),
+                ("AUDIO", "aud"),
+            ]
+        );
+    }
+
+    #[test]
+    fn picks_the_default_rendition_of_the_leanest_variant() {
+        let (variants, renditions) = master(
+            r#"#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="hi",NAME="en",DEFAULT=YES,URI="audio/hi/en.m3u8"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="lo",NAME="en",DEFAULT=NO,URI="audio/lo/en.m3u8"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="lo",NAME="de",DEFAULT=YES,URI="audio/lo/de.m3u8"
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID="subs",NAME="en",URI="subs/en.m3u8"
+#EXT-X-STREAM-INF:BANDWIDTH=2000000,CODECS="avc1.640028,mp4a.40.2",AUDIO="hi"
+video/hi.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=500000,CODECS="avc1.4d401e,mp4a.40.2",AUDIO="lo"
+video/lo.m3u8
+"#,
+        );
+        assert_eq!(variants.len(), 2);
+        assert_eq!(renditions.len(), 3);
+        assert!(variants.iter().all(|variant| !variant.audio_only));
+        assert_eq!(
+            pick_audio(&variants, &renditions).unwrap().as_str(),
+            "https://example.com/live/audio/lo/de.m3u8"
+        );
+    }
+
+    #[test]
+    fn prefers_audio_only_variants_without_renditions() {
+        let (variants, renditions) = master(
+            r#"#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=300000,CODECS="avc1.4d401e,mp4a.40.2"
+video.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=64000,CODECS="mp4a.40.5"
+audio/64k.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=128000,CODECS="mp4a.40.2"
+audio/128k.m3u8
+"#,
+        );
+        assert_eq!(
+            pick_audio(&variants, &renditions).unwrap().as_str(),
+            "https://example.com/live/audio/128k.m3u8"
+        );
+    }
+
+    #[test]
+    fn live_streams_start_near_the_edge() {
+        let segments: String = (0..6)
+            .map(|i| format!("#EXTINF:6.0,\nseg{i}.ts\n"))
+            .collect();
+        let live =
+            format!("#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXT-X-MEDIA-SEQUENCE:100\n{segments}");
+        assert_eq!(media(&live).start(), 103);
+
+        let ended = format!("{live}#EXT-X-ENDLIST\n");
+        assert_eq!(media(&ended).start(), 100);
+
+        let short = "#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:7\n#EXTINF:6.0,\na.ts\n#EXTINF:6.0,\nb.ts\n";
+        assert_eq!(media(short).start(), 7);
+    }
+
+    const PMT_PID: u16 = 0x100;
+    const AUDIO_PID: u16 = 0x101;
+
+    /// A transport stream packet with an adaptation field of `adaptation` bytes, if any, in
+    /// front of `payload`, padded with stuffing bytes
+    fn packet(pid: u16, unit_start: bool, adaptation: Option<usize>, payload: &[u8]) -> Vec<u8> {
+        let control = match adaptation {
+            Some(_) if payload.is_empty() => 0x20,
+            Some(_) => 0x30,
+            None => 0x10,
+        };
+        let mut packet = vec![
+            TS_SYNC,
+            (u8::from(unit_start) << 6) | (pid >> 8) as u8,
+            pid as u8,
+            control,
+        ];
+        if let Some(len) = adaptation {
+            packet.push(len as u8);
+            packet.extend(std::iter::repeat_n(0xff, len));
+        }
+        packet.extend_from_slice(payload);
+        packet.resize(TS_PACKET, 0xff);
+        packet
+    }
+
+    /// A PSI section behind a zero pointer field, with a dummy CRC
+    fn psi(table_id: u8, body: &[u8]) -> Vec<u8> {
+        let len = body.len() + 4;
+        let mut payload = vec![0, table_id, 0xb0 | (len >> 8) as u8, len as u8];
+        payload.extend_from_slice(body);
+        payload.extend_from_slice(&[0; 4]);
+        payload
+    }
+
+    fn transport_stream(audio: &[u8], more_audio: &[u8]) -> Vec<u8> {
+        // Program 0 points at the network information table and is passed over
+        let pat = psi(
+            0x00,
+            &[
+                0,
+                1,
+                0xc1,
+                0,
+                0,
+                0,
+                0,
+                0xe0,
+                0x10,
+                0,
+                1,
+                0xe1,
+                PMT_PID as u8,
+            ],
+        );
+        // A video stream, then ADTS audio
+        let pmt = psi(
+            0x02,
+            &[
+                0,
+                1,
+                0xc1,
+                0,
+                0,
+                0xe1,
+                0x00,
+                0xf0,
+                0x00,
+                0x1b,
+                0xe1,
+                0x00,
+                0xf0,
+                0x00,
+                STREAM_TYPE_ADTS,
+                0xe1,
+                AUDIO_PID as u8,
+                0xf0,
+                0x00,
+            ],
+        );
+        // PES header with a PTS
+        let mut pes = vec![0, 0, 1, 0xc0, 0, 0, 0x80, 0x80, 5, 0x21, 0, 1, 0, 1];
+        pes.extend_from_slice(audio);
+        let stuffing = TS_PACKET - 5 - pes.len();
+
+        let mut stream = packet(0, true, None, &pat);
+        stream.extend(packet(PMT_PID, true, None, &pmt));
+        stream.extend(packet(AUDIO_PID, true, Some(stuffing), &pes));
+        // Adaptation field alone, such as one carrying only a clock reference
+        stream.extend(packet(AUDIO_PID, false, Some(183), &[]));
+        stream.extend(packet(AUDIO_PID, false, None, more_audio));
+        stream
+    }
+
+    #[test]
+    fn ts_payload_follows_the_adaptation_field() {
+        let audio = [0xff, 0xf1, 0x50, 0x80, 0x01, 0x7f, 0xfc];
+        let more_audio = [0x5a; TS_PACKET - 4];
+        let mut demuxer = TsDemuxer::default();
+        let mut out = Vec::new();
+        demuxer.push(&transport_stream(&audio, &more_audio), &mut out);
+
+        assert_eq!(demuxer.pmt_pid, Some(PMT_PID));
+        assert_eq!(demuxer.audio, Some((AUDIO_PID, STREAM_TYPE_ADTS)));
+        assert_eq!(out, [&audio[..], &more_audio[..]].concat());
+    }
+
+    /// An ID3v2.4 tag holding `body`, with a footer if asked for
+    fn id3(body: &[u8], footer: bool) -> Vec<u8> {
+        let size = body.len() as u32;
+        let syncsafe = [21, 14, 7, 0].map(|shift| ((size >> shift) & 0x7f) as u8);
+        let flags = if footer { 0x10 } else { 0 };
+        let mut tag = [b"ID3", &[4, 0, flags][..], &syncsafe].concat();
+        tag.extend_from_slice(body);
+        if footer {
+            tag.extend([b"3DI", &[4, 0, flags][..], &syncsafe].concat());
+        }
+        tag
+    }
+
+    #[test]
+    fn id3_tags_are_skipped_with_their_footers() {
+        let audio = [0xff, 0xf1, 0x50, 0x80];
+        let body = [0x42; 200];
+        let segment = [id3(&body, true), id3(b"PRIV", false), audio.to_vec()].concat();
+        assert_eq!(skip_id3(&segment), audio);
+        assert_eq!(skip_id3(&audio), audio);
+    }
+
+    #[test]
+    fn head_tells_hls_before_the_whole_playlist() {
+        let master = "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=128000\nlow/index.m3u8\n";
+        let media = "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:6\n#EXTINF:6,\nseg0.aac\n";
+        assert_eq!(head_is_hls(master), Some(true));
+        assert_eq!(head_is_hls(&media[..25]), Some(true));
+
+        assert_eq!(
+            head_is_hls("#EXTM3U\n#EXTINF:180,Song\nsong.mp3\n"),
+            Some(false)
+        );
+        assert_eq!(head_is_hls("song.mp3\nother.mp3\n"), Some(false));
+    }
+
+    #[test]
+    fn head_waits_for_complete_lines() {
+        assert_eq!(head_is_hls(""), None);
+        assert_eq!(head_is_hls("#EXTM3U"), None);
+        assert_eq!(head_is_hls("#EXTM3U\n#EXTINF:180,Song\n"), None);
+        // A tag cut off mid-line is not yet read as one
+        assert_eq!(head_is_hls("#EXTM3U\n#EXT-X-VER"), None);
+    }
+}
@@ -0,0 +1,161 @@
+use bytes::Bytes;
+use std::sync::{Arc, Mutex};
+
+enum State {
+    /// Audio bytes left before the next metadata block
+    Audio(usize),
+    /// The next byte gives the length of the metadata block in units of 16
+    Length,
+    /// Metadata bytes left to collect
+    Metadata(usize),
+}
+
+/// Takes apart a stream whose server interleaves a metadata block after every `metaint`
+/// bytes of audio, as Shoutcast and Icecast do when asked with `Icy-MetaData: 1`
+pub(crate) struct IcyDemuxer {
+    metaint: usize,
+    state: State,
+    metadata: Vec<u8>,
+    /// Where each `StreamTitle` found is published
+    title: Arc<Mutex<Option<String>>>,
+}
+
+impl IcyDemuxer {
+    pub(crate) fn new(metaint: usize, title: Arc<Mutex<Option<String>>>) -> Self {
+        IcyDemuxer {
+            metaint,
+            state: State::Audio(metaint),
+            metadata: Vec::new(),
+            title,
+        }
+    }
+
+    /// Returns the audio in `chunk`, publishing the titles of metadata blocks on the way
+    pub(crate) fn push(&mut self, chunk: &Bytes) -> Bytes {
+        let mut audio = Vec::with_capacity(chunk.len());
+        let mut rest = &chunk[..];
+
+        while !rest.is_empty() {
+            match self.state {
+                State::Audio(left) => {
+                    let take = left.min(rest.len());
+                    audio.extend_from_slice(&rest[..take]);
+                    rest = &rest[take..];
+                    self.state = if take == left {
+                        State::Length
+                    } else {
+                        State::Audio(left - take)
+                    };
+                }
+                State::Length => {
+                    let len = rest[0] as usize * 16;
+                    rest = &rest[1..];
+                    // An empty block only says nothing changed
+                    self.state = if len == 0 {
+                        State::Audio(self.metaint)
+                    } else {
+                        State::Metadata(len)
+                    };
+                }
+                State::Metadata(left) => {
+                    let take = left.min(rest.len());
+                    self.metadata.extend_from_slice(&rest[..take]);
+                    rest = &rest[take..];
+                    if take < left {
+                        self.state = State::Metadata(left - take);
+                        continue;
+                    }
+                    if let Some(title) = stream_title(&self.metadata) {
+                        *self.title.lock().unwrap() = Some(title);
+                    }
+                    self.metadata.clear();
+                    self.state = State::Audio(self.metaint);
+                }
+            }
+        }
+
+        Bytes::from(audio)
+    }
+}
+
+/// Reads `StreamTitle` out of a block such as `StreamTitle='Artist - Song';StreamUrl='';`,
+/// which is padded with zeros to a multiple of 16 bytes
+fn stream_title(metadata: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(metadata);
+    let text = text.trim_end_matches('\0');
+    let start = text.find("StreamTitle='")? + "StreamTitle='".len();
+    // Titles may hold quotes themselves, so only a quote followed by `;` ends one
+    let end = text[start..]
+        .find("';")
+        .map_or(text.len(), |end| start + end);
+    let title = text[start..end].trim_end_matches('\'').trim();
+    Some(title.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A metadata block as a server sends it: length byte, then the text padded with zeros
+    fn block(text: &str) -> Vec<u8> {
+        let len = text.len().div_ceil(16);
+        let mut block = vec![len as u8];
+        block.extend_from_slice(text.as_bytes());
+        block.resize(1 + len * 16, 0);
+        block
+    }
+
+    fn demuxer(metaint: usize) -> (IcyDemuxer, Arc<Mutex<Option<String>>>) {
+        let title = Arc::new(Mutex::new(None));
+        (IcyDemuxer::new(metaint, Arc::clone(&title)), title)
+    }
+
+    #[test]
+    fn metadata_split_across_chunks() {
+        let mut stream = b"abcd".to_vec();
+        stream.extend(block("StreamTitle='Artist - Song';"));
+        stream.extend(b"efgh");
+
+        // Every split point, including ones inside the length byte and the text
+        for split in 0..stream.len() {
+            let (mut icy, title) = demuxer(4);
+            let mut audio = icy.push(&Bytes::copy_from_slice(&stream[..split])).to_vec();
+            audio.extend_from_slice(&icy.push(&Bytes::copy_from_slice(&stream[split..])));
+            assert_eq!(audio, b"abcdefgh", "split at {split}");
+            assert_eq!(title.lock().unwrap().as_deref(), Some("Artist - Song"));
+        }
+    }
+
+    #[test]
+    fn empty_block_changes_nothing() {
+        let (mut icy, title) = demuxer(4);
+        let audio = icy.push(&Bytes::from_static(b"abcd\0efgh\0ij"));
+        assert_eq!(&audio[..], b"abcdefghij");
+        assert_eq!(*title.lock().unwrap(), None);
+    }
+
+    #[test]
+    fn title_with_quotes() {
+        let (mut icy, title) = demuxer(2);
+        let mut stream = b"ab".to_vec();
+        stream.extend(block(
+            "StreamTitle='Guns N' Roses - Don't Cry';StreamUrl='';",
+        ));
+        stream.extend(b"cd");
+
+        assert_eq!(&icy.push(&Bytes::from(stream))[..], b"abcd");
+        assert_eq!(
+            title.lock().unwrap().as_deref(),
+            Some("Guns N' Roses - Don't Cry")
+        );
+    }
+
+    #[test]
+    fn title_ending_the_block() {
+        assert_eq!(
+            stream_title(b"StreamTitle='It's Over'\0\0\0").as_deref(),
+            Some("It's Over")
+        );
+        assert_eq!(stream_title(b"StreamUrl='';\0\0\0"), None);
+    }
+}
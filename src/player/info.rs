@@ -1,6 +1,4 @@
-use std::process::exit;
-
-use crate::err;
+use crate::error::Result;
 use crate::player::metadata::MetaData;
 use crate::player::player_structs::Player;
 use lofty::probe::Probe;
@@ -15,11 +13,8 @@ impl Player {
         self.decoder.channels()
     }
 
-    pub fn metadata(&self) -> MetaData {
-        let probe = Probe::open(&self.path).unwrap_or_else(|e| {
-            err!("Failed to probe metadata for {}: {}", self.path, e);
-            exit(1);
-        });
+    pub fn metadata(&self) -> Result<MetaData> {
+        let probe = Probe::open(&self.path)?;
 
         MetaData::new(probe)
     }
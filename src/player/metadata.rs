@@ -1,44 +1,46 @@
 use lofty::file::{AudioFile, TaggedFileExt};
 use lofty::probe::Probe;
 use lofty::properties::FileProperties;
-use lofty::tag::{Accessor, Tag};
+use lofty::tag::{Accessor, ItemKey, Tag};
 use std::fs::File;
 use std::io::BufReader;
-use std::process::exit;
 use std::time::Duration;
 
-use crate::err;
+use crate::error::Result;
 
+/// Tags and audio properties of a local file
 #[derive(Clone)]
 pub struct MetaData {
     pub tag: Option<Tag>,
     pub prop: FileProperties,
     pub title: Option<String>,
+    /// Shown when the tags name no artist
+    pub artist: Option<String>,
+    /// Shown when the tags name no album
+    pub album: Option<String>,
 }
 
 impl MetaData {
-    pub fn new(probe: Probe<BufReader<File>>) -> Self {
-        let bind = match probe.read() {
-            Ok(bind) => bind,
-            Err(e) => {
-                err!("Failed to read metadata: {}", e);
-                exit(1);
-            }
-        };
+    pub fn new(probe: Probe<BufReader<File>>) -> Result<Self> {
+        let bind = probe.read()?;
 
         let Some(s) = bind.primary_tag() else {
-            return Self {
+            return Ok(Self {
                 tag: None,
                 prop: bind.properties().clone(),
                 title: None,
-            };
+                artist: None,
+                album: None,
+            });
         };
 
-        Self {
+        Ok(Self {
             tag: Some(s.clone()),
             prop: bind.properties().clone(),
             title: None,
-        }
+            artist: None,
+            album: None,
+        })
     }
 
     pub fn set_title(&mut self, title: Option<String>) {
@@ -48,27 +50,51 @@ impl MetaData {
     pub fn title(&self) -> Option<String> {
         if self.title.is_some() {
             self.title.clone()
-        }else if let Some(tag) = &self.tag.clone() {
+        } else if let Some(tag) = &self.tag.clone() {
             tag.title().as_ref().map(|title| title.to_string())
-        }else {
+        } else {
             None
         }
     }
 
+    /// Sets the artist to fall back on when the tags name none
+    pub fn set_artist(&mut self, artist: Option<String>) {
+        self.artist = artist
+    }
+
     pub fn artist(&self) -> Option<String> {
-        if let Some(tag) = &self.tag.clone() {
-            tag.artist().as_ref().map(|artist| artist.to_string())
-        } else {
-            None
-        }
+        self.tag
+            .as_ref()
+            .and_then(|tag| tag.artist().map(|artist| artist.to_string()))
+            .or_else(|| self.artist.clone())
+    }
+
+    /// Sets the album to fall back on when the tags name none
+    pub fn set_album(&mut self, album: Option<String>) {
+        self.album = album
     }
 
     pub fn album(&self) -> Option<String> {
-        if let Some(tag) = &self.tag.clone() {
-            tag.album().as_ref().map(|album| album.to_string())
-        } else {
-            None
-        }
+        self.tag
+            .as_ref()
+            .and_then(|tag| tag.album().map(|album| album.to_string()))
+            .or_else(|| self.album.clone())
+    }
+
+    pub fn track_number(&self) -> Option<u32> {
+        self.tag.as_ref().and_then(|tag| tag.track())
+    }
+
+    pub fn disc(&self) -> Option<u32> {
+        self.tag.as_ref().and_then(|tag| tag.disk())
+    }
+
+    /// ReplayGain adjustment in dB and the matching peak, read from the given tags
+    pub fn replay_gain(&self, gain: &ItemKey, peak: &ItemKey) -> Option<(f32, Option<f32>)> {
+        let tag = self.tag.as_ref()?;
+        let gain = tag.get_string(gain).and_then(parse_number)?;
+        let peak = tag.get_string(peak).and_then(parse_number);
+        Some((gain, peak))
     }
 
     pub fn duration(&self) -> Duration {
@@ -85,3 +111,42 @@ impl MetaData {
         None
     }
 }
+
+/// Reads values such as "-6.54 dB" or "0.988312"
+fn parse_number(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let value = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value);
+    value.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_with_and_without_db() {
+        assert_eq!(parse_number("-6.54 dB"), Some(-6.54));
+        assert_eq!(parse_number("+2.10 dB"), Some(2.1));
+        assert_eq!(parse_number("-6.54dB"), Some(-6.54));
+        assert_eq!(parse_number("-6.54 db"), Some(-6.54));
+        assert_eq!(parse_number("0.988312"), Some(0.988312));
+    }
+
+    #[test]
+    fn surrounding_whitespace() {
+        assert_eq!(parse_number("  -6.54 dB  "), Some(-6.54));
+        assert_eq!(parse_number("\t1.0\n"), Some(1.0));
+        assert_eq!(parse_number("-3   dB"), Some(-3.0));
+    }
+
+    #[test]
+    fn not_numbers() {
+        assert_eq!(parse_number(""), None);
+        assert_eq!(parse_number("dB"), None);
+        assert_eq!(parse_number("loud"), None);
+        assert_eq!(parse_number("-6.54 DB"), None);
+    }
+}
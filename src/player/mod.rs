@@ -1,4 +1,13 @@
+pub mod eq;
+pub mod fade;
+pub mod hls;
+mod icy;
 pub mod info;
 pub mod metadata;
+pub mod output;
 pub mod play;
 pub mod player_structs;
+pub mod replaygain;
+pub mod stereo;
+pub mod stretch;
+pub mod url;
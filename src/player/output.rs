@@ -0,0 +1,229 @@
+use crate::error::{Error, Result};
+use rodio::cpal::traits::HostTrait;
+use rodio::cpal::{self, BufferSize};
+use rodio::mixer::{self, Mixer};
+use rodio::{Device, DeviceTrait, OutputStream, OutputStreamBuilder, StreamError};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const RENDER_CHANNELS: u16 = 2;
+const RENDER_SAMPLE_RATE: u32 = 44_100;
+/// How often the render thread takes the audio that became due out of the mixer
+const RENDER_INTERVAL: Duration = Duration::from_millis(10);
+/// How often the WAV header is brought up to date, so an interrupted render is still playable
+const RENDER_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+type WavWriter = hound::WavWriter<BufWriter<File>>;
+
+/// Which device to open and how to configure its stream; unset fields keep the device defaults
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct OutputConfig {
+    /// Name of the device, or its index as listed by [`devices`]
+    pub device: Option<String>,
+    /// Sample rate in Hz
+    pub sample_rate: Option<u32>,
+    /// Buffer size in frames
+    pub buffer_size: Option<u32>,
+}
+
+/// An output device of the system's audio host
+pub struct DeviceInfo {
+    pub name: String,
+    pub is_default: bool,
+}
+
+/// Lists the output devices in the order their indexes refer to
+pub fn devices() -> Result<Vec<DeviceInfo>> {
+    let host = cpal::default_host();
+    let default = host.default_output_device().and_then(|d| d.name().ok());
+
+    let devices = host
+        .output_devices()?
+        .map(|device| {
+            let name = device.name().unwrap_or_else(|_| String::from("<unknown>"));
+            DeviceInfo {
+                is_default: default.as_ref() == Some(&name),
+                name,
+            }
+        })
+        .collect();
+    Ok(devices)
+}
+
+/// Finds a device by its exact name, or failing that by its index
+fn find_device(name: &str) -> Result<Device> {
+    let mut devices: Vec<_> = cpal::default_host().output_devices()?.collect();
+
+    if let Some(i) = devices
+        .iter()
+        .position(|d| d.name().is_ok_and(|n| n == name))
+    {
+        return Ok(devices.swap_remove(i));
+    }
+
+    match name.parse::<usize>() {
+        Ok(i) if i < devices.len() => Ok(devices.swap_remove(i)),
+        _ => Err(Error::NoSuchDevice(name.to_string())),
+    }
+}
+
+/// The audio output shared by every track in a session
+pub struct Output {
+    mixer: Mixer,
+    /// Kept open for as long as the output is in use when playing on a device
+    _stream: Option<OutputStream>,
+    render: Option<Render>,
+}
+
+/// Drains the mixer in real time on a thread of its own, so playback is paced as on a sound card
+struct Render {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<hound::Result<()>>>,
+}
+
+impl Output {
+    /// Opens the system's default output device
+    pub fn open_default() -> Result<Self> {
+        let mut stream = rodio::OutputStreamBuilder::open_default_stream()?;
+        stream.log_on_drop(false);
+
+        Ok(Output {
+            mixer: stream.mixer().clone(),
+            _stream: Some(stream),
+            render: None,
+        })
+    }
+
+    /// Opens the device described by `config`.
+    /// Without any settings this is [`Output::open_default`], which falls back to other devices.
+    pub fn open(config: &OutputConfig) -> Result<Self> {
+        if *config == OutputConfig::default() {
+            return Self::open_default();
+        }
+
+        let device = match &config.device {
+            Some(name) => find_device(name)?,
+            None => cpal::default_host()
+                .default_output_device()
+                .ok_or(StreamError::NoDevice)?,
+        };
+
+        let mut builder = OutputStreamBuilder::from_device(device)?;
+        if let Some(sample_rate) = config.sample_rate {
+            builder = builder.with_sample_rate(sample_rate);
+        }
+        if let Some(frames) = config.buffer_size {
+            builder = builder.with_buffer_size(BufferSize::Fixed(frames));
+        }
+
+        let mut stream = builder.open_stream()?;
+        stream.log_on_drop(false);
+
+        Ok(Output {
+            mixer: stream.mixer().clone(),
+            _stream: Some(stream),
+            render: None,
+        })
+    }
+
+    /// Writes everything played to `path` as a 32-bit float, 44.1kHz stereo WAV file
+    pub fn wav<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let spec = hound::WavSpec {
+            channels: RENDER_CHANNELS,
+            sample_rate: RENDER_SAMPLE_RATE,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let writer = hound::WavWriter::create(path, spec)?;
+
+        Ok(Self::render(Some(writer)))
+    }
+
+    /// Discards everything played, for machines without an audio device
+    pub fn null() -> Self {
+        Self::render(None)
+    }
+
+    fn render(mut writer: Option<WavWriter>) -> Self {
+        let (mixer, mut source) = mixer::mixer(RENDER_CHANNELS, RENDER_SAMPLE_RATE);
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+
+        let thread = thread::spawn(move || {
+            let start = Instant::now();
+            let mut last_flush = start;
+            let mut frames = 0;
+
+            while !stopped.load(Ordering::Relaxed) {
+                let due = (start.elapsed().as_secs_f64() * RENDER_SAMPLE_RATE as f64) as u64;
+                for _ in frames..due {
+                    for _ in 0..RENDER_CHANNELS {
+                        // The mixer yields nothing while no sink is connected; that time is left out
+                        if let Some(sample) = source.next()
+                            && let Some(writer) = &mut writer
+                        {
+                            writer.write_sample(sample)?;
+                        }
+                    }
+                }
+                frames = due;
+
+                if let Some(writer) = &mut writer
+                    && last_flush.elapsed() >= RENDER_FLUSH_INTERVAL
+                {
+                    writer.flush()?;
+                    last_flush = Instant::now();
+                }
+
+                thread::sleep(RENDER_INTERVAL);
+            }
+
+            match writer {
+                Some(writer) => writer.finalize(),
+                None => Ok(()),
+            }
+        });
+
+        Output {
+            mixer,
+            _stream: None,
+            render: Some(Render {
+                stop,
+                thread: Some(thread),
+            }),
+        }
+    }
+
+    pub fn mixer(&self) -> &Mixer {
+        &self.mixer
+    }
+
+    /// Stops the output, finishing the WAV file when rendering to one
+    pub fn finish(mut self) -> Result<()> {
+        match &mut self.render {
+            Some(render) => render.stop(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Render {
+    fn stop(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.thread.take() {
+            Some(thread) => Ok(thread.join().unwrap()?),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Render {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
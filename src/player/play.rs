@@ -1,69 +1,309 @@
-use std::time::Duration;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-use crate::err;
+use crate::player::fade::{Fade, FadeControl};
 use crate::player::player_structs::Player;
-use rodio::{OutputStream, Sink};
+use crate::player::stretch::MediaClock;
+use rodio::Sink;
+use rodio::mixer::Mixer;
 
+/// Ramp used to declick pausing, resuming, skipping and stopping unless another is set
+pub const DEFAULT_DECLICK: Duration = Duration::from_millis(20);
+/// Longest declick ramp, since pausing and skipping have to wait for it
+pub const MAX_DECLICK: Duration = Duration::from_millis(200);
+pub const SKIP_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Controls for a track started with [`Player::play`]
 pub struct MusicPlay {
-    sink: Sink,
-    _stream_handle: OutputStream,
+    current: Strip,
+    /// Media position of the track playing on the current sink, which differs from its
+    /// playing time whenever the speed is not 1
+    clock: MediaClock,
+    mixer: Mixer,
+    /// The sink being faded out by a crossfade, kept alive until it goes silent
+    outgoing: Option<Strip>,
+    ab_loop: Option<AbLoop>,
+    declick: Duration,
 }
 
-impl Player {
-    pub fn play(self) -> MusicPlay {
-        let mut stream_handle =
-            rodio::OutputStreamBuilder::open_default_stream().unwrap_or_else(|e| {
-                err!("Failed to open stream: {}", e);
-                std::process::exit(1);
-            });
-        stream_handle.log_on_drop(false);
+/// Section of the current track that playback keeps returning to
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AbLoop {
+    pub start: Duration,
+    /// Until this is set, playback goes on past the start as usual
+    pub end: Option<Duration>,
+}
 
-        let sink = rodio::Sink::connect_new(stream_handle.mixer());
+impl Player {
+    /// Starts playing on `mixer` and returns the controls for the playback
+    pub fn play(self, mixer: &Mixer) -> MusicPlay {
+        let current = Strip::connect(mixer, 1.0, 1.0);
+        let clock = MediaClock::default();
 
-        sink.append(self.decoder);
+        current.sink.append(self.source(&clock));
 
         MusicPlay {
+            current,
+            clock,
+            mixer: mixer.clone(),
+            outgoing: None,
+            ab_loop: None,
+            declick: DEFAULT_DECLICK,
+        }
+    }
+}
+
+/// A sink and the two fades between it and the mixer: `level` carries crossfades and the
+/// sleep timer, `declick` the short ramps that keep pauses and stops from popping
+pub(crate) struct Strip {
+    pub(crate) sink: Sink,
+    pub(crate) level: FadeControl,
+    declick: FadeControl,
+    /// When the declick ramp gets to silence, once it has been asked to
+    silent_at: Mutex<Option<Instant>>,
+}
+
+impl Strip {
+    pub(crate) fn connect(mixer: &Mixer, level: f32, declick: f32) -> Self {
+        let silent_at = (declick == 0.0).then(Instant::now);
+        let (sink, output) = Sink::new();
+        let (fade, level) = Fade::new(output, level);
+        let (fade, declick) = Fade::new(fade, declick);
+        mixer.add(fade);
+        Strip {
             sink,
-            _stream_handle: stream_handle,
+            level,
+            declick,
+            silent_at: Mutex::new(silent_at),
         }
     }
+
+    /// Ramps to silence unless already on the way there, and returns how much longer it takes
+    pub(crate) fn silence(&self, over: Duration) -> Duration {
+        let mut silent_at = self.silent_at.lock().unwrap();
+        let at = *silent_at.get_or_insert_with(|| {
+            self.declick.ramp_to(0.0, over);
+            // A little longer than the ramp, since it only starts at the next frame the mixer pulls
+            Instant::now() + over + over / 2
+        });
+        at.saturating_duration_since(Instant::now())
+    }
+
+    pub(crate) fn unsilence(&self, over: Duration) {
+        *self.silent_at.lock().unwrap() = None;
+        self.declick.ramp_to(1.0, over);
+    }
+}
+
+/// A player whose strips ramp to silence before it pauses, skips or stops
+pub trait Declick {
+    /// Starts the ramp to silence, unless it is already under way, and returns how much
+    /// longer it takes
+    fn silence(&self) -> Duration;
+}
+
+/// Ramps `player` to silence, then runs `then` on it. The ramp is waited out without
+/// holding the lock, so the output loop and the keys carry on meanwhile, and the pause,
+/// skip or stop that `then` makes finds nothing left to wait for.
+pub async fn when_silent<P: Declick, T>(player: &Mutex<P>, then: impl FnOnce(&mut P) -> T) -> T {
+    let wait = player.lock().unwrap().silence();
+    tokio::time::sleep(wait).await;
+    then(&mut player.lock().unwrap())
+}
+
+impl Declick for MusicPlay {
+    /// Ramps everything playing, including a track being crossfaded out
+    fn silence(&self) -> Duration {
+        self.strips()
+            .map(|strip| strip.silence(self.declick))
+            .max()
+            .unwrap_or_default()
+    }
 }
 
 impl MusicPlay {
-    pub fn is_empty(&self) -> bool {
-        self.sink.empty()
+    /// Starts `player` on a sink of its own and fades it in while the current track fades out
+    pub fn crossfade_to(&mut self, player: Player, over: Duration) {
+        let paused = self.is_paused();
+        // Silent while paused, just like the sink it takes over from
+        let next = Strip::connect(&self.mixer, 0.0, if paused { 0.0 } else { 1.0 });
+        next.sink.set_volume(self.current.sink.volume());
+        if paused {
+            next.sink.pause();
+        }
+        let clock = MediaClock::default();
+        next.sink.append(player.source(&clock));
+
+        next.level.ramp_to(1.0, over);
+        self.current.level.fade_out(over);
+
+        self.outgoing = Some(std::mem::replace(&mut self.current, next));
+        self.clock = clock;
+    }
+
+    /// Queues another track right behind the current one so it starts without a gap
+    /// Only the source being played advances the sink's clock, so it follows the handover.
+    pub fn append(&self, player: Player) {
+        self.current.sink.append(player.source(&self.clock));
+    }
+
+    /// Number of tracks left in the sink, including the one playing now
+    pub fn queued(&self) -> usize {
+        self.current.sink.len()
+    }
+
+    /// Every sink still making sound
+    fn strips(&self) -> impl Iterator<Item = &Strip> {
+        std::iter::once(&self.current).chain(&self.outgoing)
+    }
+
+    /// Fades out the track playing now and fades in the one appended behind it once it has
+    /// taken over, or leaves the sink empty if there is none
+    pub fn skip_one(&self) {
+        let left = self.begin_skip();
+        while !self.skipped(left) {
+            std::thread::sleep(SKIP_POLL_INTERVAL);
+        }
     }
 
+    /// Fades out and drops the track playing now, returning how many tracks are left for
+    /// [`skipped`](Self::skipped) to wait for
+    pub fn begin_skip(&self) -> usize {
+        let left = self.queued().saturating_sub(1);
+        // The track behind shares the clock, and with it the loop points
+        self.clock.clear_loop();
+        std::thread::sleep(self.silence());
+        self.current.sink.skip_one();
+        left
+    }
+
+    /// Whether the sink is down to `left` tracks after [`begin_skip`](Self::begin_skip),
+    /// fading in the next one once it is
+    pub fn skipped(&self, left: usize) -> bool {
+        if self.queued() > left {
+            return false;
+        }
+        // A paused sink stays silent until it is resumed
+        if !self.is_paused() {
+            self.current.unsilence(self.declick);
+        }
+        true
+    }
+
+    /// Fades out, then pauses
     pub fn pause(&mut self) {
-        self.sink.pause();
+        if self.is_paused() {
+            return;
+        }
+        std::thread::sleep(self.silence());
+        for strip in self.strips() {
+            strip.sink.pause();
+        }
     }
 
+    /// Resumes, then fades in
     pub fn resume(&mut self) {
-        self.sink.play();
+        for strip in self.strips() {
+            strip.sink.play();
+            strip.unsilence(self.declick);
+        }
+    }
+
+    /// Fades out and stops for good; nothing plays on these controls afterwards
+    pub fn stop(&mut self) {
+        std::thread::sleep(self.silence());
+        for strip in self.strips() {
+            strip.sink.stop();
+        }
     }
 
     pub fn is_paused(&self) -> bool {
-        self.sink.is_paused()
+        self.current.sink.is_paused()
+    }
+
+    /// Ramps the output of the current track towards `target` in the sample path,
+    /// on top of the volume
+    pub fn ramp_volume(&self, target: f32, over: Duration) {
+        self.current.level.ramp_to(target, over);
+    }
+
+    /// Sets how long pausing, resuming, skipping and stopping take to ramp
+    pub fn set_declick(mut self, over: Duration) -> Self {
+        self.declick = over;
+        self
     }
 
     pub fn get_volume(&self) -> f32 {
-        self.sink.volume()
+        self.current.sink.volume()
     }
 
     pub fn set_volume(self, vol: f32) -> Self {
-        self.sink.set_volume(vol);
+        self.current.sink.set_volume(vol);
         self
     }
 
     pub fn set_volume_mut(&mut self, vol: f32) {
-        self.sink.set_volume(vol);
+        for strip in self.strips() {
+            strip.sink.set_volume(vol);
+        }
     }
 
     pub fn seek(&self, dur: Duration) -> Result<(), rodio::source::SeekError> {
-        self.sink.try_seek(dur)
+        self.current.sink.try_seek(dur)
     }
 
+    /// Position within the current track in media time, whatever the speed
     pub fn get_pos(&self) -> std::time::Duration {
-        self.sink.get_pos()
+        self.clock.get()
+    }
+
+    pub fn ab_loop(&self) -> Option<AbLoop> {
+        match self.ab_loop {
+            // Dropped by the source when it could not seek back
+            Some(AbLoop { end: Some(_), .. }) if self.clock.ab_loop().is_none() => None,
+            ab_loop => ab_loop,
+        }
+    }
+
+    /// Whether a closed loop keeps playback from reaching the end of the track
+    pub fn is_looping(&self) -> bool {
+        matches!(self.ab_loop(), Some(AbLoop { end: Some(_), .. }))
+    }
+
+    /// Marks the current position as the start of a loop, replacing any loop there was
+    pub fn set_loop_start(&mut self) -> Duration {
+        let start = self.get_pos();
+        self.clock.clear_loop();
+        self.ab_loop = Some(AbLoop { start, end: None });
+        start
+    }
+
+    /// Closes the loop at the current position, jumps back to its start and returns both ends.
+    /// From then on the source itself wraps around at the end, without a gap.
+    /// Returns `None` without a start to loop back to or when the position is not past it.
+    pub fn set_loop_end(
+        &mut self,
+    ) -> Option<Result<(Duration, Duration), rodio::source::SeekError>> {
+        let start = self.ab_loop?.start;
+        let end = self.get_pos();
+        if end <= start {
+            return None;
+        }
+
+        if let Err(e) = self.seek(start) {
+            self.ab_loop = None;
+            return Some(Err(e));
+        }
+        self.ab_loop = Some(AbLoop {
+            start,
+            end: Some(end),
+        });
+        self.clock.set_loop(start, end);
+        Some(Ok((start, end)))
+    }
+
+    pub fn clear_loop(&mut self) {
+        self.ab_loop = None;
+        self.clock.clear_loop();
     }
 }
@@ -1,55 +1,90 @@
-use rodio::Decoder;
 use rodio::decoder::DecoderBuilder;
+use rodio::source::{Amplify, SeekError};
+use rodio::{Decoder, Source};
+use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
-use std::{fs::File, process::exit};
+use std::time::Duration;
 
-use crate::err;
+use crate::error::Result;
+use crate::player::eq::{EqControl, Equalizer};
+use crate::player::stereo::{Stereo, StereoControl};
+use crate::player::stretch::{MediaClock, SpeedControl, TimeStretch};
 
+/// Everything between a [`Player`]'s decoder and its sink
+pub type PlayerSource = Stereo<Equalizer<Amplify<TimeStretch<Decoder<BufReader<File>>>>>>;
+
+/// A local audio file, opened and ready to play
 pub struct Player {
     pub decoder: Decoder<BufReader<File>>,
     pub path: String,
-}
-
-impl Clone for Player {
-    fn clone(&self) -> Self {
-        Player::new(&self.path)
-    }
+    /// Linear gain of this track alone, applied on top of the volume of the sink it plays on
+    pub gain: f32,
+    pub eq: EqControl,
+    pub speed: SpeedControl,
+    pub stereo: StereoControl,
+    /// Where playback begins, moved by [`Player::seek`]
+    pub start: Duration,
 }
 
 impl Player {
-    pub fn new<P: AsRef<Path>>(path: P) -> Self {
-        let path = path.as_ref().to_str().unwrap().to_string();
-
-        let file = File::open(&path).unwrap_or_else(|e| {
-            err!("Failed to open {}: {}", path, e);
-            exit(1);
-        });
-
-        let buff = BufReader::new(file.try_clone().unwrap());
-        let len = file
-            .metadata()
-            .unwrap_or_else(|e| {
-                err!("Failed to get metadata from {}: {}", &path, e);
-                exit(1);
-            })
-            .len();
+    /// Opens `path` and builds a seekable decoder for it
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_string_lossy().to_string();
+
+        let file = File::open(&path)?;
+
+        let buff = BufReader::new(file.try_clone()?);
+        let len = file.metadata()?.len();
 
         let decoder = DecoderBuilder::new()
             .with_seekable(true)
             .with_data(buff)
             .with_byte_len(len)
-            .build()
-            .unwrap_or_else(|e| {
-                err!("Failed to build decoder: {}", e);
-                exit(1);
-            });
-
-        // let decoder = Decoder::new(BufReader::new(file)).unwrap_or_else(|e| {
-        //     err!("Failed to decode {}: {}", path, e);
-        //     exit(1);
-        // });
-
-        Player { decoder, path }
+            .build()?;
+
+        Ok(Player {
+            decoder,
+            path,
+            gain: 1.0,
+            eq: EqControl::default(),
+            speed: SpeedControl::default(),
+            stereo: StereoControl::default(),
+            start: Duration::ZERO,
+        })
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
+    pub fn set_equalizer(&mut self, eq: EqControl) {
+        self.eq = eq;
+    }
+
+    pub fn set_speed(&mut self, speed: SpeedControl) {
+        self.speed = speed;
+    }
+
+    pub fn set_stereo(&mut self, stereo: StereoControl) {
+        self.stereo = stereo;
+    }
+
+    /// Makes playback begin at `pos` instead of the start of the track
+    pub fn seek(&mut self, pos: Duration) -> std::result::Result<(), SeekError> {
+        self.decoder.try_seek(pos)?;
+        self.start = pos;
+        Ok(())
+    }
+
+    /// The decoder at the set speed with the track's own gain, the equalizer and the stereo
+    /// controls applied, reporting its media position to `clock`
+    pub fn source(self, clock: &MediaClock) -> PlayerSource {
+        let stretched = self
+            .speed
+            .apply(self.decoder, clock)
+            .starting_at(self.start);
+        self.stereo
+            .apply(self.eq.apply(stretched.amplify(self.gain)))
     }
 }
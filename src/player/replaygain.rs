@@ -0,0 +1,142 @@
+use crate::player::metadata::MetaData;
+use lofty::tag::ItemKey;
+
+/// Which of the ReplayGain values to apply
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GainMode {
+    Track,
+    /// Album gain, or the track gain for files that only carry that
+    Album,
+}
+
+/// How ReplayGain tags are turned into a volume adjustment
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ReplayGain {
+    pub mode: GainMode,
+    /// Added to every tagged gain, in dB
+    pub preamp: f32,
+}
+
+impl ReplayGain {
+    /// Linear factor for a track, lowered where needed so its peak does not clip.
+    /// Tracks without gain tags are left as they are.
+    pub fn factor(&self, metadata: &MetaData) -> f32 {
+        let track =
+            || metadata.replay_gain(&ItemKey::ReplayGainTrackGain, &ItemKey::ReplayGainTrackPeak);
+        let values = match self.mode {
+            GainMode::Track => track(),
+            GainMode::Album => metadata
+                .replay_gain(&ItemKey::ReplayGainAlbumGain, &ItemKey::ReplayGainAlbumPeak)
+                .or_else(track),
+        };
+
+        let Some((gain, peak)) = values else {
+            return 1.0;
+        };
+
+        let factor = 10f32.powf((gain + self.preamp) / 20.0);
+        match peak {
+            Some(peak) if peak > 0.0 => factor.min(1.0 / peak),
+            _ => factor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lofty::properties::FileProperties;
+    use lofty::tag::{Tag, TagType};
+
+    fn metadata(items: &[(ItemKey, &str)]) -> MetaData {
+        let mut tag = Tag::new(TagType::VorbisComments);
+        for (key, value) in items {
+            tag.insert_text(key.clone(), value.to_string());
+        }
+        MetaData {
+            tag: Some(tag),
+            prop: FileProperties::default(),
+            title: None,
+            artist: None,
+            album: None,
+        }
+    }
+
+    fn gain(mode: GainMode, preamp: f32) -> ReplayGain {
+        ReplayGain { mode, preamp }
+    }
+
+    /// Within 0.01 dB
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual / expected - 1.0).abs() < 1e-3,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn untagged_tracks_stay_as_they_are() {
+        let untagged = MetaData {
+            tag: None,
+            ..metadata(&[])
+        };
+        assert_eq!(gain(GainMode::Album, 6.0).factor(&untagged), 1.0);
+        assert_eq!(gain(GainMode::Track, 6.0).factor(&metadata(&[])), 1.0);
+        // A peak alone says nothing about the gain
+        let peak_only = metadata(&[(ItemKey::ReplayGainTrackPeak, "0.5")]);
+        assert_eq!(gain(GainMode::Track, 0.0).factor(&peak_only), 1.0);
+    }
+
+    #[test]
+    fn gain_and_preamp_in_db() {
+        let tagged = metadata(&[(ItemKey::ReplayGainTrackGain, "-6.02 dB")]);
+        assert_near(gain(GainMode::Track, 0.0).factor(&tagged), 0.5);
+        assert_near(gain(GainMode::Track, 6.02).factor(&tagged), 1.0);
+        assert_near(gain(GainMode::Track, -6.02).factor(&tagged), 0.25);
+    }
+
+    #[test]
+    fn peak_keeps_it_from_clipping() {
+        let loud = metadata(&[
+            (ItemKey::ReplayGainTrackGain, "+6.02 dB"),
+            (ItemKey::ReplayGainTrackPeak, "0.8"),
+        ]);
+        assert_near(gain(GainMode::Track, 0.0).factor(&loud), 1.25);
+
+        // Turning it down never runs into the peak
+        let quiet = metadata(&[
+            (ItemKey::ReplayGainTrackGain, "-6.02 dB"),
+            (ItemKey::ReplayGainTrackPeak, "0.8"),
+        ]);
+        assert_near(gain(GainMode::Track, 0.0).factor(&quiet), 0.5);
+
+        let zero_peak = metadata(&[
+            (ItemKey::ReplayGainTrackGain, "+6.02 dB"),
+            (ItemKey::ReplayGainTrackPeak, "0"),
+        ]);
+        assert_near(gain(GainMode::Track, 0.0).factor(&zero_peak), 2.0);
+    }
+
+    #[test]
+    fn album_gain_with_its_own_peak() {
+        let tagged = metadata(&[
+            (ItemKey::ReplayGainTrackGain, "-12.04 dB"),
+            (ItemKey::ReplayGainTrackPeak, "1.0"),
+            (ItemKey::ReplayGainAlbumGain, "+6.02 dB"),
+            (ItemKey::ReplayGainAlbumPeak, "0.4"),
+        ]);
+        assert_near(gain(GainMode::Album, 0.0).factor(&tagged), 2.0);
+        assert_near(gain(GainMode::Track, 0.0).factor(&tagged), 0.25);
+    }
+
+    #[test]
+    fn album_falls_back_to_track() {
+        let track_only = metadata(&[
+            (ItemKey::ReplayGainTrackGain, "+6.02 dB"),
+            (ItemKey::ReplayGainTrackPeak, "0.8"),
+        ]);
+        assert_near(gain(GainMode::Album, 0.0).factor(&track_only), 1.25);
+    }
+}
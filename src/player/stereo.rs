@@ -0,0 +1,282 @@
+use rodio::Source;
+use rodio::source::SeekError;
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+
+struct Shared {
+    /// -1 is fully left, 1 fully right
+    balance: AtomicU32,
+    mono: AtomicBool,
+    swap: AtomicBool,
+}
+
+/// Balance, mono downmix and channel swap, read by the [`Stereo`]s it applies on every frame
+#[derive(Clone)]
+pub struct StereoControl {
+    shared: Arc<Shared>,
+}
+
+impl StereoControl {
+    pub fn new() -> Self {
+        StereoControl {
+            shared: Arc::new(Shared {
+                balance: AtomicU32::new(0f32.to_bits()),
+                mono: AtomicBool::new(false),
+                swap: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Sets the balance, clamped to -1 (left) ..= 1 (right), and returns what was set
+    pub fn set_balance(&self, balance: f32) -> f32 {
+        let balance = balance.clamp(-1.0, 1.0);
+        self.shared
+            .balance
+            .store(balance.to_bits(), Ordering::Relaxed);
+        balance
+    }
+
+    pub fn balance(&self) -> f32 {
+        f32::from_bits(self.shared.balance.load(Ordering::Relaxed))
+    }
+
+    /// Turns the mono downmix on or off and returns whether it is now on
+    pub fn toggle_mono(&self) -> bool {
+        !self.shared.mono.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    pub fn mono(&self) -> bool {
+        self.shared.mono.load(Ordering::Relaxed)
+    }
+
+    /// Swaps left and right, or stops swapping them, and returns whether they are now swapped
+    pub fn toggle_swap(&self) -> bool {
+        !self.shared.swap.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    pub fn swap(&self) -> bool {
+        self.shared.swap.load(Ordering::Relaxed)
+    }
+
+    /// Wraps `inner` so it comes out as stereo following this control
+    pub fn apply<S: Source>(&self, inner: S) -> Stereo<S> {
+        Stereo {
+            inner,
+            control: self.clone(),
+            layout: (0, Vec::new()),
+            right: None,
+        }
+    }
+}
+
+impl Default for StereoControl {
+    fn default() -> Self {
+        StereoControl::new()
+    }
+}
+
+/// Folds any number of channels down to stereo, then applies the swap, mono downmix and
+/// balance of its control
+pub struct Stereo<S> {
+    inner: S,
+    control: StereoControl,
+    /// Channel count of the inner source and how much of each channel goes left and right
+    layout: (u16, Vec<[f32; 2]>),
+    /// Right sample of the frame whose left sample was returned last
+    right: Option<f32>,
+}
+
+/// Left and right weight of each channel in the usual WAV/FLAC order: front left and right,
+/// then center, LFE and surrounds as far as the count goes. The LFE is left out, as in
+/// ITU-R BS.775 downmixes, and surrounds and the center come in at -3 dB.
+fn fold_down(channels: u16) -> Vec<[f32; 2]> {
+    const C: f32 = FRAC_1_SQRT_2;
+    let weights: Vec<[f32; 2]> = match channels {
+        0 | 1 => vec![[1.0, 1.0]],
+        2 => vec![[1.0, 0.0], [0.0, 1.0]],
+        3 => vec![[1.0, 0.0], [0.0, 1.0], [C, C]],
+        // Quad: front pair then rear pair
+        4 => vec![[1.0, 0.0], [0.0, 1.0], [C, 0.0], [0.0, C]],
+        5 => vec![[1.0, 0.0], [0.0, 1.0], [C, C], [C, 0.0], [0.0, C]],
+        6 => vec![
+            [1.0, 0.0],
+            [0.0, 1.0],
+            [C, C],
+            [0.0, 0.0],
+            [C, 0.0],
+            [0.0, C],
+        ],
+        // 7.1 and beyond: every further channel alternates between the sides
+        _ => {
+            let mut weights = vec![[1.0, 0.0], [0.0, 1.0], [C, C], [0.0, 0.0]];
+            weights.extend((4..channels).map(|i| if i % 2 == 0 { [C, 0.0] } else { [0.0, C] }));
+            weights
+        }
+    };
+
+    // Scaled so a channel at full scale on every input can not clip either side
+    let sum = |side: usize| weights.iter().map(|w| w[side]).sum::<f32>();
+    let (left, right) = (sum(0), sum(1));
+    weights
+        .iter()
+        .map(|w| [w[0] / left, w[1] / right])
+        .collect()
+}
+
+impl<S: Source> Iterator for Stereo<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(right) = self.right.take() {
+            return Some(right);
+        }
+
+        let channels = self.inner.channels();
+        if self.layout.0 != channels {
+            self.layout = (channels, fold_down(channels));
+        }
+
+        let (mut left, mut right) = (0.0, 0.0);
+        for (i, weights) in self.layout.1.iter().enumerate() {
+            let sample = self.inner.next();
+            // A source ending mid-frame ends here too, unless there is a frame to finish
+            let sample = match sample {
+                Some(sample) => sample,
+                None if i == 0 => return None,
+                None => 0.0,
+            };
+            left += sample * weights[0];
+            right += sample * weights[1];
+        }
+
+        if self.control.swap() {
+            std::mem::swap(&mut left, &mut right);
+        }
+        if self.control.mono() {
+            let mid = (left + right) / 2.0;
+            (left, right) = (mid, mid);
+        }
+        let balance = self.control.balance();
+        left *= (1.0 - balance).min(1.0);
+        right *= (1.0 + balance).min(1.0);
+
+        self.right = Some(right);
+        Some(left)
+    }
+}
+
+impl<S: Source> Source for Stereo<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.right = None;
+        self.inner.try_seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    /// Left and right of each frame that `control` makes of interleaved `samples`
+    fn play(control: &StereoControl, channels: u16, samples: &[f32]) -> Vec<[f32; 2]> {
+        let source = SamplesBuffer::new(channels, 48_000, samples.to_vec());
+        let out: Vec<f32> = control.apply(source).collect();
+        out.chunks(2).map(|frame| [frame[0], frame[1]]).collect()
+    }
+
+    fn assert_frame(actual: [f32; 2], expected: [f32; 2]) {
+        assert!(
+            (actual[0] - expected[0]).abs() < 1e-6 && (actual[1] - expected[1]).abs() < 1e-6,
+            "{:?} is not {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn every_layout_sums_to_unity_on_each_side() {
+        for channels in 1..=8 {
+            let weights = fold_down(channels);
+            assert_eq!(weights.len(), channels.max(1) as usize);
+            for side in 0..2 {
+                let sum: f32 = weights.iter().map(|w| w[side]).sum();
+                assert!(
+                    (sum - 1.0).abs() < 1e-6,
+                    "{} channels, side {}",
+                    channels,
+                    side
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn surround_folds_down_without_the_lfe() {
+        let control = StereoControl::new();
+        // Only the LFE of 5.1 carries anything
+        let frames = play(&control, 6, &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+        assert_frame(frames[0], [0.0, 0.0]);
+        // Center alone lands on both sides alike
+        let frames = play(&control, 6, &[0.0, 0.0, 1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(frames[0][0], frames[0][1]);
+        assert!(frames[0][0] > 0.0);
+    }
+
+    #[test]
+    fn mono_sources_play_on_both_sides() {
+        let frames = play(&StereoControl::new(), 1, &[0.5, -0.25]);
+        assert_frame(frames[0], [0.5, 0.5]);
+        assert_frame(frames[1], [-0.25, -0.25]);
+    }
+
+    #[test]
+    fn mono_downmix_keeps_the_level() {
+        let control = StereoControl::new();
+        assert!(control.toggle_mono());
+        let frames = play(&control, 2, &[1.0, 1.0, 1.0, 0.0, 0.5, -0.5]);
+        assert_frame(frames[0], [1.0, 1.0]);
+        assert_frame(frames[1], [0.5, 0.5]);
+        assert_frame(frames[2], [0.0, 0.0]);
+        assert!(!control.toggle_mono());
+    }
+
+    #[test]
+    fn hard_left_and_right() {
+        let control = StereoControl::new();
+        assert_eq!(control.set_balance(-1.5), -1.0);
+        assert_frame(play(&control, 2, &[0.8, 0.6])[0], [0.8, 0.0]);
+        assert_eq!(control.set_balance(1.0), 1.0);
+        assert_frame(play(&control, 2, &[0.8, 0.6])[0], [0.0, 0.6]);
+        // Half way only turns the other side down
+        control.set_balance(-0.5);
+        assert_frame(play(&control, 2, &[0.8, 0.6])[0], [0.8, 0.3]);
+    }
+
+    #[test]
+    fn swap_trades_sides() {
+        let control = StereoControl::new();
+        assert!(control.toggle_swap());
+        assert_frame(play(&control, 2, &[0.8, 0.6])[0], [0.6, 0.8]);
+        // Balance applies to the sides as they come out
+        control.set_balance(1.0);
+        assert_frame(play(&control, 2, &[0.8, 0.6])[0], [0.0, 0.8]);
+    }
+}
@@ -0,0 +1,543 @@
+use rodio::Source;
+use rodio::source::SeekError;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 3.0;
+
+/// Output advances by this many milliseconds per processed segment
+const HOP_MS: u32 = 20;
+/// How far from its nominal position a segment may be taken to line up with the previous one
+const TOLERANCE_MS: u32 = 10;
+
+/// Speed that each [`TimeStretch`] it applies reads anew for every segment
+#[derive(Clone)]
+pub struct SpeedControl {
+    speed: Arc<AtomicU32>,
+}
+
+impl SpeedControl {
+    pub fn new(speed: f32) -> Self {
+        let control = SpeedControl {
+            speed: Arc::new(AtomicU32::new(1f32.to_bits())),
+        };
+        control.set(speed);
+        control
+    }
+
+    /// Sets the speed, clamped to [`MIN_SPEED`]..=[`MAX_SPEED`], and returns what was set
+    pub fn set(&self, speed: f32) -> f32 {
+        let speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        self.speed.store(speed.to_bits(), Ordering::Relaxed);
+        speed
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.speed.load(Ordering::Relaxed))
+    }
+
+    /// Wraps `inner` so it plays at this control's speed, reporting its media position to `clock`
+    pub fn apply<S: Source>(&self, inner: S, clock: &MediaClock) -> TimeStretch<S> {
+        let channels = inner.channels().max(1) as usize;
+        let sample_rate = inner.sample_rate().max(1);
+        let hop = (sample_rate * HOP_MS / 1000).max(1) as usize;
+        let window = (0..2 * hop)
+            .map(|i| {
+                let phase = std::f32::consts::PI * i as f32 / hop as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+
+        TimeStretch {
+            inner,
+            control: self.clone(),
+            clock: clock.clone(),
+            channels,
+            sample_rate,
+            hop,
+            tolerance: (sample_rate * TOLERANCE_MS / 1000) as usize,
+            window,
+            input: Vec::new(),
+            input_start: 0,
+            inner_done: false,
+            nominal: 0.0,
+            previous: None,
+            overlap: Vec::new(),
+            overlap_len: 0,
+            output: Vec::new(),
+            output_pos: 0,
+            base: Duration::ZERO,
+            stretching: false,
+            played: 0,
+            finished: false,
+        }
+    }
+}
+
+impl Default for SpeedControl {
+    fn default() -> Self {
+        SpeedControl::new(1.0)
+    }
+}
+
+/// Media position of whichever [`TimeStretch`] source is currently being played through it,
+/// and the A-B loop that source wraps around
+#[derive(Clone, Default)]
+pub struct MediaClock {
+    micros: Arc<AtomicU64>,
+    loop_start: Arc<AtomicU64>,
+    /// Zero while no loop is closed
+    loop_end: Arc<AtomicU64>,
+}
+
+impl MediaClock {
+    fn set(&self, pos: Duration) {
+        self.micros.store(pos.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Duration {
+        Duration::from_micros(self.micros.load(Ordering::Relaxed))
+    }
+
+    /// Makes the source jump back to `start` at the exact frame it reaches `end`, which lies
+    /// past `start`
+    pub(crate) fn set_loop(&self, start: Duration, end: Duration) {
+        self.loop_start
+            .store(start.as_micros() as u64, Ordering::Relaxed);
+        self.loop_end
+            .store(end.as_micros() as u64, Ordering::Release);
+    }
+
+    pub(crate) fn clear_loop(&self) {
+        self.loop_end.store(0, Ordering::Release);
+    }
+
+    /// Start and end of the closed loop, if there is one
+    pub(crate) fn ab_loop(&self) -> Option<(Duration, Duration)> {
+        let end = self.loop_end.load(Ordering::Acquire);
+        (end != 0).then(|| {
+            let start = self.loop_start.load(Ordering::Relaxed);
+            (Duration::from_micros(start), Duration::from_micros(end))
+        })
+    }
+}
+
+/// Changes speed without changing pitch (WSOLA): overlapping segments are taken from the input
+/// at the rate of the speed, each shifted slightly to where it best continues the previous one.
+/// Until the speed first leaves 1 the input is passed through untouched, so gapless joins stay
+/// sample-accurate.
+pub struct TimeStretch<S> {
+    inner: S,
+    control: SpeedControl,
+    clock: MediaClock,
+    channels: usize,
+    sample_rate: u32,
+    /// Output frames per segment; segments are twice as long and overlap by half
+    hop: usize,
+    tolerance: usize,
+    window: Vec<f32>,
+    /// Interleaved input, starting at frame `input_start` of the media
+    input: Vec<f32>,
+    input_start: usize,
+    inner_done: bool,
+    /// Media frame the next segment should ideally start at
+    nominal: f64,
+    /// Media frame the previous segment started at
+    previous: Option<usize>,
+    /// Second half of the previous windowed segment, still to be added to the next one;
+    /// empty until the first segment after a start or seek
+    overlap: Vec<f32>,
+    /// Frames of `overlap` that hold input rather than padding past its end
+    overlap_len: usize,
+    output: Vec<f32>,
+    output_pos: usize,
+    /// Media position that frame 0 refers to, moved by seeking
+    base: Duration,
+    /// Whether segments are being stretched rather than the input passed through
+    stretching: bool,
+    /// Samples passed through since `base`
+    played: usize,
+    finished: bool,
+}
+
+impl<S: Source> TimeStretch<S> {
+    /// Reports positions from `pos` on, for an inner source that has already been seeked there
+    pub fn starting_at(mut self, pos: Duration) -> Self {
+        self.base = pos;
+        self
+    }
+
+    /// Reads from the inner source until media frame `end` is buffered or it runs out
+    fn fill_to(&mut self, end: usize) {
+        while !self.inner_done && self.input_start + self.input.len() / self.channels < end {
+            match self.inner.next() {
+                Some(sample) => self.input.push(sample),
+                None => self.inner_done = true,
+            }
+        }
+    }
+
+    fn frame(&self, frame: usize, channel: usize) -> f32 {
+        frame
+            .checked_sub(self.input_start)
+            .and_then(|i| self.input.get(i * self.channels + channel))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    fn mono(&self, from: usize, len: usize) -> Vec<f32> {
+        (from..from + len)
+            .map(|frame| (0..self.channels).map(|c| self.frame(frame, c)).sum())
+            .collect()
+    }
+
+    /// Start within `from..=to` whose first `hop` frames look most like those at `natural`
+    fn best_match(&self, natural: usize, from: usize, to: usize) -> usize {
+        let target = self.mono(natural, self.hop);
+        let candidates = self.mono(from, to - from + self.hop);
+
+        // Every other frame and offset is enough to find the alignment
+        let score = |offset: usize| {
+            let (mut cross, mut energy) = (0.0, 0.0);
+            for i in (0..self.hop).step_by(2) {
+                let x = candidates[offset + i];
+                cross += x * target[i];
+                energy += x * x;
+            }
+            if energy > 0.0 {
+                cross / f32::sqrt(energy)
+            } else {
+                0.0
+            }
+        };
+        let offset = (0..=to - from)
+            .step_by(2)
+            .max_by(|&a, &b| score(a).total_cmp(&score(b)))
+            .unwrap_or(0);
+        from + offset
+    }
+
+    /// Start of the closed loop, if media frame `frame` lies at or past its end
+    fn loop_start_at(&self, frame: usize) -> Option<Duration> {
+        let (start, end) = self.clock.ab_loop()?;
+        (self.base + self.frames_to_duration(frame) >= end).then_some(start)
+    }
+
+    /// Starts the loop over from `start`; a source that cannot seek drops the loop instead
+    fn wrap_to(&mut self, start: Duration) -> bool {
+        if self.inner.try_seek(start).is_err() {
+            self.clock.clear_loop();
+            return false;
+        }
+
+        self.input.clear();
+        self.input_start = 0;
+        self.inner_done = false;
+        self.nominal = 0.0;
+        self.previous = None;
+        self.base = start;
+        self.played = 0;
+        self.clock.set(start);
+        true
+    }
+
+    /// Called between frames while passing through: wraps around the loop, reports the
+    /// position every hop and switches to stretching at the frame the speed first leaves 1
+    fn frame_boundary(&mut self) {
+        let frame = self.played / self.channels;
+        if let Some(start) = self.loop_start_at(frame) {
+            self.join_loop(start);
+            return;
+        }
+        // Whatever is left of a join has already been taken from the inner source
+        let joining = self.output_pos < self.output.len();
+        if self.control.get() != 1.0 && !joining {
+            self.stretching = true;
+            self.input.clear();
+            self.input_start = frame;
+            self.nominal = frame as f64;
+            self.previous = None;
+            self.overlap.clear();
+        } else if frame.is_multiple_of(self.hop) {
+            self.clock.set(self.base + self.frames_to_duration(frame));
+        }
+    }
+
+    /// Jumps from the loop end back to `start` while passing through. The hop past the end
+    /// fades out under the first hop of the loop, just as overlapping segments would, and
+    /// the mix is played from `output` before the inner source carries on.
+    fn join_loop(&mut self, start: Duration) {
+        let len = self.hop * self.channels;
+        let tail: Vec<f32> = self.inner.by_ref().take(len).collect();
+        self.output = if self.wrap_to(start) {
+            let head: Vec<f32> = self.inner.by_ref().take(len).collect();
+            (0..head.len())
+                .map(|i| {
+                    let frame = i / self.channels;
+                    let fading = tail.get(i).copied().unwrap_or(0.0);
+                    head[i] * self.window[frame] + fading * self.window[self.hop + frame]
+                })
+                .collect()
+        } else {
+            tail
+        };
+        self.output_pos = 0;
+    }
+
+    /// Produces the next `hop` frames of output, or returns false at the end of the input
+    fn process_segment(&mut self) -> bool {
+        // The tail of the last segment before the loop end still overlaps the first one after
+        // it, crossfading the two
+        if let Some(start) = self.loop_start_at(self.nominal.round() as usize) {
+            self.wrap_to(start);
+        }
+
+        let speed = self.control.get() as f64;
+        let nominal = self.nominal.round() as usize;
+
+        let start = match self.previous {
+            // At normal speed the natural continuation is exactly the nominal position
+            Some(previous) if speed != 1.0 => {
+                let from = nominal.saturating_sub(self.tolerance).max(self.input_start);
+                let to = nominal + self.tolerance;
+                self.fill_to(to + 2 * self.hop);
+                self.best_match(previous + self.hop, from, to)
+            }
+            _ => nominal,
+        };
+
+        self.fill_to(start + 2 * self.hop);
+        let available = self.input_start + self.input.len() / self.channels;
+
+        if self.overlap.is_empty() {
+            // As if a previous segment had ended exactly here, so the first one is not faded in:
+            // the rising and falling halves of the window add up to 1
+            for i in 0..self.hop {
+                for c in 0..self.channels {
+                    let sample = self.frame(start + i, c) * self.window[self.hop + i];
+                    self.overlap.push(sample);
+                }
+            }
+            self.overlap_len = available.saturating_sub(start).min(self.hop);
+        }
+
+        // Past the end of the input segments are only padding, which is not played
+        let len = available
+            .saturating_sub(start)
+            .min(self.hop)
+            .max(self.overlap_len);
+        if len == 0 {
+            return false;
+        }
+
+        let mut segment = vec![0.0; 2 * self.hop * self.channels];
+        for i in 0..2 * self.hop {
+            for c in 0..self.channels {
+                segment[i * self.channels + c] = self.frame(start + i, c) * self.window[i];
+            }
+        }
+
+        let (head, tail) = segment.split_at(self.hop * self.channels);
+        self.output = head
+            .iter()
+            .zip(&self.overlap)
+            .take(len * self.channels)
+            .map(|(a, b)| a + b)
+            .collect();
+        self.output_pos = 0;
+        self.overlap = tail.to_vec();
+        self.overlap_len = available.saturating_sub(start + self.hop).min(self.hop);
+
+        self.clock.set(self.base + self.frames_to_duration(start));
+        self.previous = Some(start);
+        self.nominal += self.hop as f64 * speed;
+
+        // Nothing before the earliest frame the next search can reach is needed again
+        let keep_from = start.min((self.nominal as usize).saturating_sub(self.tolerance));
+        let drop = keep_from.saturating_sub(self.input_start);
+        if drop > 0 {
+            self.input
+                .drain(..(drop * self.channels).min(self.input.len()));
+            self.input_start += drop;
+        }
+        true
+    }
+
+    fn frames_to_duration(&self, frames: usize) -> Duration {
+        Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)
+    }
+}
+
+impl<S: Source> Iterator for TimeStretch<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.stretching && !self.finished {
+            if self.played.is_multiple_of(self.channels) {
+                self.frame_boundary();
+            }
+            if !self.stretching {
+                let sample = match self.output.get(self.output_pos) {
+                    Some(&sample) => {
+                        self.output_pos += 1;
+                        Some(sample)
+                    }
+                    None => self.inner.next(),
+                };
+                match sample {
+                    Some(_) => self.played += 1,
+                    None => self.finished = true,
+                }
+                return sample;
+            }
+        }
+
+        if self.output_pos == self.output.len() && (self.finished || !self.process_segment()) {
+            self.finished = true;
+            return None;
+        }
+
+        let sample = self.output[self.output_pos];
+        self.output_pos += 1;
+        Some(sample)
+    }
+}
+
+impl<S: Source> Source for TimeStretch<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Playing time depends on the speed, which may change at any moment
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+
+        self.input.clear();
+        self.input_start = 0;
+        self.inner_done = false;
+        self.nominal = 0.0;
+        self.previous = None;
+        self.overlap.clear();
+        self.overlap_len = 0;
+        self.output.clear();
+        self.output_pos = 0;
+        self.base = pos;
+        self.stretching = false;
+        self.played = 0;
+        self.finished = false;
+        self.clock.set(pos);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const RATE: u32 = 48_000;
+
+    /// Two seconds of a stereo sine, 440 Hz on the left and 660 Hz on the right
+    fn sine() -> SamplesBuffer {
+        let samples = (0..2 * RATE as usize)
+            .flat_map(|frame| {
+                let t = frame as f32 / RATE as f32;
+                [440.0, 660.0].map(|freq| (std::f32::consts::TAU * freq * t).sin() * 0.5)
+            })
+            .collect::<Vec<_>>();
+        SamplesBuffer::new(2, RATE, samples)
+    }
+
+    fn stretched(speed: f32) -> (TimeStretch<SamplesBuffer>, MediaClock) {
+        let clock = MediaClock::default();
+        (SpeedControl::new(speed).apply(sine(), &clock), clock)
+    }
+
+    #[test]
+    fn normal_speed_passes_through() {
+        let (stretch, clock) = stretched(1.0);
+        let out: Vec<f32> = stretch.collect();
+        assert!(out == sine().collect::<Vec<_>>());
+        // Reported every hop, so the last one before the end
+        let pos = clock.get().as_secs_f64();
+        assert!((1.98..=2.0).contains(&pos), "clock at {}", pos);
+    }
+
+    #[test]
+    fn length_scales_with_speed() {
+        let input = sine().count() as f64;
+        for speed in [0.5, 2.0, 1.5] {
+            let (stretch, _) = stretched(speed);
+            let out = stretch.collect::<Vec<_>>();
+            assert!(out.len().is_multiple_of(2));
+            // The last segment stops where the input does rather than being stretched,
+            // which leaves the output up to a couple of hops short
+            let expected = input / speed as f64;
+            let hop = (2 * RATE * HOP_MS / 1000) as f64;
+            assert!(
+                (out.len() as f64 - expected).abs() <= 2.0 * hop,
+                "{} samples at {}x, expected {}",
+                out.len(),
+                speed,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn clock_tells_media_time() {
+        for speed in [0.5, 2.0] {
+            let (mut stretch, clock) = stretched(speed);
+            // Half a second of output
+            stretch.by_ref().take(RATE as usize).for_each(drop);
+            let media = 0.5 * speed as f64;
+            let pos = clock.get().as_secs_f64();
+            assert!(
+                (pos - media).abs() <= 2.0 * HOP_MS as f64 / 1000.0,
+                "clock at {} after 0.5s at {}x",
+                pos,
+                speed
+            );
+        }
+    }
+
+    #[test]
+    fn speed_change_mid_stream() {
+        let speed = SpeedControl::new(1.0);
+        let clock = MediaClock::default();
+        let mut stretch = speed.apply(sine(), &clock);
+        let passed: Vec<f32> = stretch.by_ref().take(RATE as usize).collect();
+        assert!(passed == sine().take(RATE as usize).collect::<Vec<_>>());
+
+        // The remaining 1.5 seconds of media play in 0.75
+        speed.set(2.0);
+        let rest = stretch.count() as f64;
+        let expected = 1.5 * RATE as f64;
+        assert!((rest - expected).abs() <= (2 * RATE * HOP_MS / 1000) as f64);
+    }
+
+    #[test]
+    fn seek_moves_the_clock() {
+        let (mut stretch, clock) = stretched(2.0);
+        stretch.by_ref().take(1000).for_each(drop);
+        stretch.try_seek(Duration::from_millis(1500)).unwrap();
+        assert_eq!(clock.get(), Duration::from_millis(1500));
+        // Half a second of media left, at double speed
+        let rest = stretch.count() as f64;
+        assert!((rest - 0.25 * 2.0 * RATE as f64).abs() <= (2 * RATE * HOP_MS / 1000) as f64);
+    }
+}
@@ -0,0 +1,994 @@
+use crate::error::{Error, Result};
+use crate::player::eq::EqControl;
+use crate::player::hls;
+use crate::player::icy::IcyDemuxer;
+use crate::player::play::{DEFAULT_DECLICK, Declick, Strip};
+use crate::player::stereo::StereoControl;
+use crate::player::stretch::{MediaClock, SpeedControl};
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use hyper::{Request, StatusCode};
+use hyper_tls::HttpsConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+use rodio::Source;
+use rodio::mixer::Mixer;
+use rodio::source::SeekError;
+use std::io::{Read, Result as IoResult};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, TryLockError};
+use std::time::Duration;
+use symphonia::core::audio::{AudioBufferRef, SampleBuffer};
+use symphonia::core::codecs::{CODEC_TYPE_NULL, DecoderOptions};
+use symphonia::core::errors::{Error as SymphoniaError, SeekErrorKind};
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use url::Url;
+
+pub(crate) type HttpClient = Client<HttpsConnector<HttpConnector>, String>;
+pub(crate) type StreamError = Box<dyn std::error::Error + Send + Sync>;
+
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+const MAX_REDIRECTS: usize = 10;
+/// Frames a [`SharedSource`] takes from the decoder at a time
+const SHARED_CHUNK_FRAMES: usize = 256;
+
+/// Where a stream can be fetched again from any byte, when the server accepts ranges
+struct Remote {
+    client: HttpClient,
+    url: String,
+    len: u64,
+    runtime: Handle,
+    downloaded: Arc<Mutex<u64>>,
+    error: Arc<Mutex<Option<String>>>,
+}
+
+struct StreamReader {
+    rx: mpsc::Receiver<Bytes>,
+    current: Option<Bytes>,
+    offset: usize,
+    buffer: Vec<u8>,
+    prebuffer_size: usize,
+    prebuffered: bool,
+    /// Byte of the stream the next read starts at
+    pos: u64,
+    remote: Option<Remote>,
+}
+
+impl symphonia::core::io::MediaSource for StreamReader {
+    fn is_seekable(&self) -> bool {
+        self.remote.is_some()
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.remote.as_ref().map(|remote| remote.len)
+    }
+}
+
+impl std::io::Seek for StreamReader {
+    fn seek(&mut self, from: std::io::SeekFrom) -> IoResult<u64> {
+        let Some(remote) = &self.remote else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "StreamReader does not support seeking",
+            ));
+        };
+
+        let target = match from {
+            std::io::SeekFrom::Start(offset) => Some(offset),
+            std::io::SeekFrom::End(offset) => remote.len.checked_add_signed(offset),
+            std::io::SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        }
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek before the start of the stream",
+            )
+        })?;
+        if target == self.pos {
+            return Ok(target);
+        }
+
+        // Dropping the old receiver makes the download feeding it give up
+        let (tx, rx) = mpsc::channel::<Bytes>(1024);
+        *remote.downloaded.lock().unwrap() = target;
+        if target < remote.len {
+            remote.runtime.spawn(download_from(
+                remote.client.clone(),
+                remote.url.clone(),
+                target,
+                tx,
+                Arc::clone(&remote.downloaded),
+                Arc::clone(&remote.error),
+            ));
+        }
+
+        self.rx = rx;
+        self.current = None;
+        self.offset = 0;
+        self.buffer.clear();
+        self.prebuffered = true;
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+impl StreamReader {
+    fn new(rx: mpsc::Receiver<Bytes>, remote: Option<Remote>) -> Self {
+        Self {
+            rx,
+            current: None,
+            offset: 0,
+            buffer: Vec::new(),
+            prebuffer_size: 64 * 1024, // 512KB -> 64KB に削減
+            prebuffered: false,
+            pos: 0,
+            remote,
+        }
+    }
+
+    fn prebuffer(&mut self) -> IoResult<()> {
+        if self.prebuffered {
+            return Ok(());
+        }
+
+        while self.buffer.len() < self.prebuffer_size {
+            match self.rx.blocking_recv() {
+                Some(chunk) => {
+                    self.buffer.extend_from_slice(&chunk);
+                }
+                None => {
+                    if !self.buffer.is_empty() {
+                        break;
+                    }
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "Stream ended before prebuffering completed",
+                    ));
+                }
+            }
+        }
+
+        self.prebuffered = true;
+        Ok(())
+    }
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let read = self.fill(buf)?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl StreamReader {
+    /// Copies out whatever has arrived, waiting for the download if nothing has yet
+    fn fill(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if !self.prebuffered {
+            self.prebuffer()?;
+        }
+
+        if !self.buffer.is_empty() {
+            let to_copy = self.buffer.len().min(buf.len());
+            buf[..to_copy].copy_from_slice(&self.buffer[..to_copy]);
+            self.buffer.drain(..to_copy);
+            return Ok(to_copy);
+        }
+
+        loop {
+            if let Some(chunk) = &self.current
+                && self.offset < chunk.len()
+            {
+                let remaining = chunk.len() - self.offset;
+                let to_copy = remaining.min(buf.len());
+
+                buf[..to_copy].copy_from_slice(&chunk[self.offset..self.offset + to_copy]);
+
+                self.offset += to_copy;
+                return Ok(to_copy);
+            }
+
+            match self.rx.blocking_recv() {
+                Some(chunk) => {
+                    self.current = Some(chunk);
+                    self.offset = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+pub struct SymphoniaSource {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn symphonia::core::codecs::Decoder>,
+    track_id: u32,
+    sample_rate: u32,
+    channels: u16,
+    current_samples: Vec<f32>,
+    current_index: usize,
+    /// Frames still to drop after a seek landed before the position asked for
+    skip_frames: u64,
+    finished: Arc<Mutex<bool>>,
+}
+
+impl SymphoniaSource {
+    fn new(
+        format: Box<dyn FormatReader>,
+        decoder: Box<dyn symphonia::core::codecs::Decoder>,
+        track_id: u32,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Self {
+        Self {
+            format,
+            decoder,
+            track_id,
+            sample_rate,
+            channels,
+            current_samples: Vec::new(),
+            current_index: 0,
+            skip_frames: 0,
+            finished: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    fn decode_next_packet(&mut self) -> bool {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    *self.finished.lock().unwrap() = true;
+                    return false;
+                }
+                Err(_) => {
+                    *self.finished.lock().unwrap() = true;
+                    return false;
+                }
+            };
+
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            match self.decoder.decode(&packet) {
+                Ok(decoded) => {
+                    self.current_samples = convert_samples(decoded);
+                    let frames = self.current_samples.len() as u64 / self.channels.max(1) as u64;
+                    let skipped = self.skip_frames.min(frames);
+                    self.skip_frames -= skipped;
+                    self.current_index = skipped as usize * self.channels as usize;
+                    return true;
+                }
+                Err(_) => continue,
+            }
+        }
+    }
+
+    #[allow(unused)]
+    pub fn is_finished(&self) -> bool {
+        *self.finished.lock().unwrap()
+    }
+}
+
+fn convert_samples(buffer: AudioBufferRef) -> Vec<f32> {
+    let spec = *buffer.spec();
+    let duration = buffer.frames();
+
+    let mut sample_buf = SampleBuffer::<f32>::new(duration as u64, spec);
+    sample_buf.copy_interleaved_ref(buffer);
+    sample_buf.samples().to_vec()
+}
+
+impl Iterator for SymphoniaSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.current_index < self.current_samples.len() {
+                let sample = self.current_samples[self.current_index];
+                self.current_index += 1;
+                return Some(sample);
+            }
+
+            if !self.decode_next_packet() {
+                return None;
+            }
+        }
+    }
+}
+
+impl Source for SymphoniaSource {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> std::result::Result<(), SeekError> {
+        let seeked = self
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: pos.into(),
+                    track_id: Some(self.track_id),
+                },
+            )
+            .map_err(|e| match e {
+                SymphoniaError::SeekError(SeekErrorKind::ForwardOnly) => SeekError::NotSupported {
+                    underlying_source: std::any::type_name::<Self>(),
+                },
+                e => SeekError::Other(Box::new(e)),
+            })?;
+
+        // The demuxer lands on the packet holding the position; the timestamps of audio
+        // tracks count frames, so the difference is what to drop from the next packets
+        self.decoder.reset();
+        self.current_samples.clear();
+        self.current_index = 0;
+        self.skip_frames = seeked.required_ts.saturating_sub(seeked.actual_ts);
+        *self.finished.lock().unwrap() = false;
+        Ok(())
+    }
+}
+
+/// Plays the decoder that a [`Seeker`] also holds on to. Seeking a stream means waiting for
+/// the server, so the seeker pauses the sink first and raises `seeking`; should the audio
+/// thread still get here before the pause takes hold, it plays silence rather than wait for
+/// the decoder, and the seek then throws that away along with the position it counted.
+struct SharedSource {
+    source: Arc<Mutex<SymphoniaSource>>,
+    seeking: Arc<AtomicBool>,
+    channels: u16,
+    sample_rate: u32,
+    chunk: Vec<f32>,
+    pos: usize,
+}
+
+impl Iterator for SharedSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.chunk.len() {
+            self.chunk.clear();
+            self.pos = 0;
+            let channels = self.channels.max(1) as usize;
+            if self.seeking.load(Ordering::Acquire) {
+                self.chunk.resize(channels, 0.0);
+            } else {
+                match self.source.try_lock() {
+                    Ok(mut source) => self
+                        .chunk
+                        .extend(source.by_ref().take(SHARED_CHUNK_FRAMES * channels)),
+                    Err(TryLockError::WouldBlock) => self.chunk.resize(channels, 0.0),
+                    Err(TryLockError::Poisoned(_)) => return None,
+                }
+            }
+        }
+
+        let sample = *self.chunk.get(self.pos)?;
+        self.pos += 1;
+        Some(sample)
+    }
+}
+
+impl Source for SharedSource {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    /// The decoder has already been seeked by [`Seeker::seek`]; all that is left is to
+    /// drop what was taken from it before
+    fn try_seek(&mut self, _pos: Duration) -> std::result::Result<(), SeekError> {
+        self.chunk.clear();
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+/// Plays an HTTP(S) audio stream while it downloads
+pub struct UrlPlayer {
+    strip: Arc<Strip>,
+    declick: Duration,
+    source: Option<Arc<Mutex<SymphoniaSource>>>,
+    /// Raised while a [`Seeker`] has the decoder
+    seeking: Arc<AtomicBool>,
+    sample_rate: u32,
+    channel: u32,
+    downloaded_bytes: Arc<Mutex<u64>>,
+    total_bytes: Arc<Mutex<Option<u64>>>,
+    error: Arc<Mutex<Option<String>>>,
+    /// Media position of the stream, advanced by the source as it plays
+    clock: MediaClock,
+    seekable: bool,
+    /// Latest `StreamTitle` sent by an ICY server
+    stream_title: Arc<Mutex<Option<String>>>,
+    station: Option<String>,
+    genre: Option<String>,
+}
+
+impl Declick for UrlPlayer {
+    fn silence(&self) -> Duration {
+        self.strip.silence(self.declick)
+    }
+}
+
+impl UrlPlayer {
+    pub fn new(mixer: &Mixer) -> Self {
+        Self {
+            strip: Arc::new(Strip::connect(mixer, 1.0, 1.0)),
+            declick: DEFAULT_DECLICK,
+            sample_rate: 0,
+            channel: 0,
+            source: None,
+            seeking: Arc::new(AtomicBool::new(false)),
+            downloaded_bytes: Arc::new(Mutex::new(0)),
+            total_bytes: Arc::new(Mutex::new(None)),
+            error: Arc::new(Mutex::new(None)),
+            clock: MediaClock::default(),
+            seekable: false,
+            stream_title: Arc::new(Mutex::new(None)),
+            station: None,
+            genre: None,
+        }
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.strip.sink.set_volume(volume);
+    }
+
+    pub fn get_volume(&self) -> f32 {
+        self.strip.sink.volume()
+    }
+
+    /// Fades out, then pauses
+    pub fn pause(&self) {
+        if self.is_paused() {
+            return;
+        }
+        std::thread::sleep(self.silence());
+        self.strip.sink.pause();
+    }
+
+    /// Resumes, then fades in
+    pub fn resume(&self) {
+        self.strip.sink.play();
+        self.strip.unsilence(self.declick);
+    }
+
+    /// Fades out and stops for good
+    pub fn stop(&self) {
+        std::thread::sleep(self.silence());
+        self.strip.sink.stop();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.strip.sink.is_paused()
+    }
+
+    /// Ramps the stream towards `target` in the sample path, on top of the volume
+    pub fn ramp_volume(&self, target: f32, over: Duration) {
+        self.strip.level.ramp_to(target, over);
+    }
+
+    /// Sets how long pausing, resuming and stopping take to ramp
+    pub fn set_declick(&mut self, over: Duration) {
+        self.declick = over;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strip.sink.empty()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.channel
+    }
+
+    pub fn get_downloaded_bytes(&self) -> u64 {
+        *self.downloaded_bytes.lock().unwrap()
+    }
+
+    pub fn get_downloaded_mb(&self) -> f64 {
+        self.get_downloaded_bytes() as f64 / 1024.0 / 1024.0
+    }
+
+    pub fn get_total_bytes(&self) -> Option<u64> {
+        *self.total_bytes.lock().unwrap()
+    }
+
+    pub fn get_total_mb(&self) -> Option<f64> {
+        self.get_total_bytes().map(|b| b as f64 / 1024.0 / 1024.0)
+    }
+
+    pub fn get_download_progress(&self) -> Option<f32> {
+        let downloaded = self.get_downloaded_bytes();
+        self.get_total_bytes()
+            .map(|total| (downloaded as f32 / total as f32) * 100.0)
+    }
+
+    /// Whether the server lets the stream be fetched again from any byte
+    pub fn is_seekable(&self) -> bool {
+        self.seekable
+    }
+
+    /// What seeks the stream without holding on to the player, if it plays at all
+    pub fn seeker(&self) -> Option<Seeker> {
+        Some(Seeker {
+            source: Arc::clone(self.source.as_ref()?),
+            strip: Arc::clone(&self.strip),
+            seeking: Arc::clone(&self.seeking),
+            declick: self.declick,
+        })
+    }
+
+    /// Position within the stream in media time, whatever the speed
+    pub fn get_pos(&self) -> Duration {
+        self.clock.get()
+    }
+
+    /// What an internet radio station says is playing now, if it says anything
+    pub fn stream_title(&self) -> Option<String> {
+        self.stream_title
+            .lock()
+            .unwrap()
+            .clone()
+            .filter(|title| !title.is_empty())
+    }
+
+    /// Station name from the `icy-name` header
+    pub fn station_name(&self) -> Option<&str> {
+        self.station.as_deref()
+    }
+
+    /// Genre from the `icy-genre` header
+    pub fn genre(&self) -> Option<&str> {
+        self.genre.as_deref()
+    }
+
+    /// The error that cut the download short, if any
+    pub fn stream_error(&self) -> Option<String> {
+        self.error.lock().unwrap().clone()
+    }
+}
+
+/// Seeks a [`UrlPlayer`]'s stream apart from the player
+pub struct Seeker {
+    source: Arc<Mutex<SymphoniaSource>>,
+    strip: Arc<Strip>,
+    seeking: Arc<AtomicBool>,
+    declick: Duration,
+}
+
+impl Seeker {
+    /// Seeks to `pos`, ramping the stream to silence and pausing it meanwhile. The decoder
+    /// blocks while the server sends the range asked for, so this belongs on a thread that
+    /// may block, such as one of `spawn_blocking`.
+    pub fn seek(self, pos: Duration) -> std::result::Result<(), SeekError> {
+        let paused = self.strip.sink.is_paused();
+        std::thread::sleep(self.strip.silence(self.declick));
+        self.strip.sink.pause();
+        self.seeking.store(true, Ordering::Release);
+
+        let result = self.source.lock().unwrap().try_seek(pos).and_then(|()| {
+            // The sink takes seeks while paused too, dropping what the chain buffered
+            // before the decoder moved
+            self.strip.sink.try_seek(pos)
+        });
+
+        self.seeking.store(false, Ordering::Release);
+        if !paused {
+            self.strip.sink.play();
+            self.strip.unsilence(self.declick);
+        }
+        result
+    }
+}
+
+/// Connects to `url`, following redirects, and starts playing it on `mixer`
+pub async fn setup_url_player(
+    mixer: &Mixer,
+    url: &str,
+    volume: f32,
+    eq: &EqControl,
+    speed: &SpeedControl,
+    stereo: &StereoControl,
+) -> Result<UrlPlayer> {
+    connect(mixer, url, volume, eq, speed, stereo)
+        .await
+        .map_err(Error::Stream)
+}
+
+/// Where the bytes of a stream come from and what is known about it before probing
+struct Feed {
+    rx: mpsc::Receiver<Bytes>,
+    remote: Option<Remote>,
+    /// Extension to probe with when the container is known up front
+    extension: Option<&'static str>,
+    total_bytes: Option<u64>,
+    downloaded: Arc<Mutex<u64>>,
+    error: Arc<Mutex<Option<String>>>,
+    stream_title: Arc<Mutex<Option<String>>>,
+    station: Option<String>,
+    genre: Option<String>,
+}
+
+async fn connect(
+    mixer: &Mixer,
+    url: &str,
+    volume: f32,
+    eq: &EqControl,
+    speed: &SpeedControl,
+    stereo: &StereoControl,
+) -> std::result::Result<UrlPlayer, StreamError> {
+    let client = client();
+
+    let (response, current_url) = get(&client, url, &[("Icy-MetaData", "1")]).await?;
+
+    let content_type = response
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok());
+    let feed = if hls::is_playlist(&current_url, content_type) {
+        let playlist = response.into_body().collect().await?.to_bytes();
+        let downloaded = Arc::new(Mutex::new(playlist.len() as u64));
+        let error = Arc::new(Mutex::new(None));
+        let (rx, extension) = hls::open(
+            client,
+            &current_url,
+            &String::from_utf8_lossy(&playlist),
+            Arc::clone(&downloaded),
+            Arc::clone(&error),
+        )
+        .await?;
+        Feed {
+            rx,
+            remote: None,
+            extension: Some(extension),
+            total_bytes: None,
+            downloaded,
+            error,
+            stream_title: Arc::new(Mutex::new(None)),
+            station: None,
+            genre: None,
+        }
+    } else {
+        direct(client, response, current_url)?
+    };
+
+    let mixer = mixer.clone();
+    let eq = eq.clone();
+    let speed = speed.clone();
+    let stereo = stereo.clone();
+    let player = std::thread::spawn(move || open(&mixer, feed, volume, &eq, &speed, &stereo))
+        .join()
+        .unwrap()?;
+
+    Ok(player)
+}
+
+/// Starts downloading the body of `response` as the stream itself
+fn direct(
+    client: HttpClient,
+    response: hyper::Response<Incoming>,
+    url: String,
+) -> std::result::Result<Feed, StreamError> {
+    let total_bytes = response
+        .headers()
+        .get("content-length")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+
+    // Ranges only help once the length is known, as symphonia seeks relative to the end
+    let accepts_ranges = response
+        .headers()
+        .get("accept-ranges")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("bytes"));
+
+    // Stations often send names in Latin-1, which `to_str` refuses
+    let icy_header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .map(|v| String::from_utf8_lossy(v.as_bytes()).trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    let metaint = icy_header("icy-metaint").and_then(|v| v.parse::<usize>().ok());
+    let station = icy_header("icy-name");
+    let genre = icy_header("icy-genre");
+    let stream_title = Arc::new(Mutex::new(None));
+    let icy = metaint
+        .filter(|&metaint| metaint > 0)
+        .map(|metaint| IcyDemuxer::new(metaint, Arc::clone(&stream_title)));
+
+    let (tx, rx) = mpsc::channel::<Bytes>(1024); // 512 -> 1024に増加
+
+    let downloaded = Arc::new(Mutex::new(0u64));
+    let error = Arc::new(Mutex::new(None));
+
+    // Byte offsets mean nothing once metadata is woven into the audio
+    let seek_by_range = accepts_ranges && icy.is_none();
+
+    tokio::spawn(download(
+        response.into_body(),
+        icy,
+        tx,
+        Arc::clone(&downloaded),
+        Arc::clone(&error),
+    ));
+
+    let remote = match total_bytes {
+        Some(len) if seek_by_range => Some(Remote {
+            client,
+            url,
+            len,
+            runtime: Handle::current(),
+            downloaded: Arc::clone(&downloaded),
+            error: Arc::clone(&error),
+        }),
+        _ => None,
+    };
+
+    Ok(Feed {
+        rx,
+        remote,
+        extension: None,
+        total_bytes,
+        downloaded,
+        error,
+        stream_title,
+        station,
+        genre,
+    })
+}
+
+/// Probes the stream coming through `feed` and starts it on a player of its own.
+/// Blocks until enough has arrived to probe, so it must not run on the async runtime.
+fn open(
+    mixer: &Mixer,
+    feed: Feed,
+    volume: f32,
+    eq: &EqControl,
+    speed: &SpeedControl,
+    stereo: &StereoControl,
+) -> std::result::Result<UrlPlayer, StreamError> {
+    let seekable = feed.remote.is_some();
+    let mut reader = StreamReader::new(feed.rx, feed.remote);
+
+    let buffered_size = 128 * 1024; // 1MB -> 128KB に削減
+
+    let mut hint = Hint::new();
+
+    reader.prebuffer()?;
+
+    if let Some(extension) = feed.extension {
+        hint.with_extension(extension);
+    } else {
+        // ファイルタイプ検出のためのバッファサイズを削減
+        let detect_size = reader.buffer.len().min(2000);
+        if let Some(kind) = infer::get(&reader.buffer[..detect_size]) {
+            match kind.mime_type() {
+                "audio/mpeg" => hint.with_extension("mp3"),
+                "audio/flac" => hint.with_extension("flac"),
+                "audio/ogg" => hint.with_extension("ogg"),
+                "audio/wav" => hint.with_extension("wav"),
+                "audio/aac" => hint.with_extension("aac"),
+                "audio/mp4" => hint.with_extension("m4a"),
+                _ => return Err("Stream is not supported mime type!".into()),
+            };
+        }
+    }
+
+    let mss = MediaSourceStream::new(
+        Box::new(reader),
+        symphonia::core::io::MediaSourceStreamOptions {
+            buffer_len: buffered_size,
+        },
+    );
+
+    let meta_opts: MetadataOptions = Default::default();
+    let fmt_opts: FormatOptions = Default::default();
+
+    let probed = symphonia::default::get_probe().format(&hint, mss, &fmt_opts, &meta_opts)?;
+
+    let format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("Track not found")?;
+
+    let track_id = track.id;
+    let codec_params = &track.codec_params;
+
+    let sample_rate = codec_params
+        .sample_rate
+        .ok_or("Samplerate is not available")?;
+    let channels = codec_params.channels.ok_or("Channels is not available")?;
+
+    let dec_opts: DecoderOptions = Default::default();
+    let decoder = symphonia::default::get_codecs().make(codec_params, &dec_opts)?;
+
+    let source = SymphoniaSource::new(
+        format,
+        decoder,
+        track_id,
+        sample_rate,
+        channels.count() as u16,
+    );
+
+    let mut player = UrlPlayer::new(mixer);
+    player.set_volume(volume);
+    player.downloaded_bytes = feed.downloaded;
+    player.error = feed.error;
+    *player.total_bytes.lock().unwrap() = feed.total_bytes;
+    player.seekable = seekable;
+    player.stream_title = feed.stream_title;
+    player.station = feed.station;
+    player.genre = feed.genre;
+    let source = Arc::new(Mutex::new(source));
+    player.source = Some(Arc::clone(&source));
+    let shared = SharedSource {
+        source,
+        seeking: Arc::clone(&player.seeking),
+        channels: channels.count() as u16,
+        sample_rate,
+        chunk: Vec::new(),
+        pos: 0,
+    };
+    let stretched = speed.apply(shared, &player.clock);
+    player.strip.sink.append(stereo.apply(eq.apply(stretched)));
+    player.channel = channels.count() as u32;
+    player.sample_rate = sample_rate;
+
+    Ok(player)
+}
+
+/// A client for fetching streams and playlists over HTTP or HTTPS
+pub(crate) fn client() -> HttpClient {
+    Client::builder(TokioExecutor::new()).build(HttpsConnector::new())
+}
+
+/// Sends a GET for `url` with `headers` on top of the user agent, following up to ten
+/// redirects, and returns the response along with the URL it finally came from
+pub(crate) async fn get(
+    client: &HttpClient,
+    url: &str,
+    headers: &[(&str, &str)],
+) -> std::result::Result<(hyper::Response<Incoming>, String), StreamError> {
+    let mut current_url = url.to_string();
+
+    for _ in 0..=MAX_REDIRECTS {
+        let mut req = Request::builder()
+            .uri(current_url.parse::<hyper::Uri>()?)
+            .header("User-Agent", USER_AGENT);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+
+        let resp = client.request(req.body(String::new())?).await?;
+        let status = resp.status();
+
+        if status.is_redirection() {
+            let location = resp
+                .headers()
+                .get("location")
+                .ok_or("Redirect without Location header")?;
+            // Relative locations count from the URL that redirected
+            current_url = Url::parse(&current_url)?
+                .join(location.to_str()?)?
+                .to_string();
+            continue;
+        }
+
+        if !status.is_success() {
+            return Err(format!("HTTP Error: {}", status).into());
+        }
+
+        return Ok((resp, current_url));
+    }
+
+    Err("Too many redirects".into())
+}
+
+/// Downloads the whole of `url` and returns it along with the URL it finally came from
+pub(crate) async fn fetch(
+    client: &HttpClient,
+    url: &str,
+) -> std::result::Result<(Bytes, String), StreamError> {
+    let (response, url) = get(client, url, &[]).await?;
+    let body = response.into_body().collect().await?.to_bytes();
+    Ok((body, url))
+}
+
+/// Passes the chunks of `body` on to `tx` as they arrive, counting them in `downloaded`,
+/// after `icy` took out any metadata woven into them
+async fn download(
+    mut body: Incoming,
+    mut icy: Option<IcyDemuxer>,
+    tx: mpsc::Sender<Bytes>,
+    downloaded: Arc<Mutex<u64>>,
+    error: Arc<Mutex<Option<String>>>,
+) {
+    while let Some(result) = body.frame().await {
+        match result {
+            Ok(frame) => {
+                if let Some(chunk) = frame.data_ref() {
+                    let audio = match &mut icy {
+                        Some(icy) => icy.push(chunk),
+                        None => chunk.clone(),
+                    };
+                    // Counted once sent, so a download given up on after a seek adds nothing
+                    if tx.send(audio).await.is_err() {
+                        break;
+                    }
+                    *downloaded.lock().unwrap() += chunk.len() as u64;
+                }
+            }
+            Err(e) => {
+                *error.lock().unwrap() = Some(e.to_string());
+                break;
+            }
+        }
+    }
+}
+
+/// Requests `url` again from byte `from` onwards and downloads the rest like [`download`]
+async fn download_from(
+    client: HttpClient,
+    url: String,
+    from: u64,
+    tx: mpsc::Sender<Bytes>,
+    downloaded: Arc<Mutex<u64>>,
+    error: Arc<Mutex<Option<String>>>,
+) {
+    let range = format!("bytes={}-", from);
+    match get(&client, &url, &[("Range", &range)]).await {
+        // Anything but the range asked for would be read as if it started at `from`
+        Ok((response, _)) if response.status() == StatusCode::PARTIAL_CONTENT => {
+            // A range that failed before this one no longer cuts the stream short
+            *error.lock().unwrap() = None;
+            download(response.into_body(), None, tx, downloaded, error).await;
+        }
+        Ok((response, _)) => {
+            *error.lock().unwrap() = Some(format!("HTTP Error: {}", response.status()));
+        }
+        Err(e) => *error.lock().unwrap() = Some(e.to_string()),
+    }
+}
@@ -0,0 +1,97 @@
+use crate::error::Result;
+use crate::m3u::{self, PlaylistEntry};
+use crate::track::Track;
+use std::collections::BTreeMap;
+use std::{fs, path::Path};
+
+/// Parses PLS text, the `[playlist]` format radio directories hand out. Entries come back
+/// in the order of their numbers, as written, without resolving them.
+pub fn parse(pls: &str) -> Vec<PlaylistEntry> {
+    let mut entries: BTreeMap<u32, PlaylistEntry> = BTreeMap::new();
+
+    for line in pls.lines().map(|l| l.trim()) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim();
+
+        // Keys are `File1`, `Title1`, `Length1` and so on; `NumberOfEntries` and
+        // `Version` have no number and are left out
+        let split = key.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+        let Ok(number) = key[split..].parse::<u32>() else {
+            continue;
+        };
+        let entry = entries.entry(number).or_insert_with(|| PlaylistEntry {
+            path: String::new(),
+            title: None,
+            duration: None,
+        });
+        match &key[..split] {
+            "file" => entry.path = value.to_string(),
+            "title" if !value.is_empty() => entry.title = Some(value.to_string()),
+            "length" => entry.duration = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    entries
+        .into_values()
+        .filter(|entry| !entry.path.is_empty())
+        .collect()
+}
+
+/// Reads a PLS playlist, resolving relative entries against the playlist's directory
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Track>> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path)?;
+    Ok(m3u::resolve(parse(&content), path))
+}
+
+/// Downloads a PLS playlist, resolving relative entries against the URL it finally came from
+pub async fn load_url(url: &str) -> Result<Vec<Track>> {
+    let (content, base) = m3u::download(url).await?;
+    Ok(m3u::resolve_url(parse(&content), &base))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_follow_their_numbers() {
+        let entries = parse(
+            "[playlist]
+File10=ten.mp3
+Title10=Ten
+File2=two.mp3
+Length2=125
+File7=seven.mp3
+Title3=No file to go with
+NumberOfEntries=3
+Version=2
+",
+        );
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["two.mp3", "seven.mp3", "ten.mp3"]);
+        assert_eq!(entries[0].duration, Some(125));
+        assert_eq!(entries[1].title, None);
+        assert_eq!(entries[2].title.as_deref(), Some("Ten"));
+    }
+
+    #[test]
+    fn keys_in_any_case() {
+        let entries = parse(
+            "[Playlist]
+file1=http://radio.example.com:8000/stream
+TITLE1=Example Radio
+LENGTH1=-1
+numberofentries=1
+",
+        );
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, "http://radio.example.com:8000/stream");
+        assert_eq!(entries[0].title.as_deref(), Some("Example Radio"));
+        assert_eq!(entries[0].duration, Some(-1));
+    }
+}
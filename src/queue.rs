@@ -0,0 +1,396 @@
+use clap::ValueEnum;
+use lofty::file::TaggedFileExt;
+use lofty::probe::Probe;
+use lofty::tag::Accessor;
+use minau::track::Track;
+use rand::seq::SliceRandom;
+use std::path::Path;
+
+/// What to play once the current track stops
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    /// The current track played to its end
+    Ended,
+    Next,
+    Previous,
+    Restart,
+    /// Zero-based index into the queue as it was given
+    Jump(usize),
+    /// Stop playing the rest of the queue
+    Quit,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Shuffle {
+    /// Shuffle individual tracks
+    Track,
+    /// Shuffle whole albums, keeping each album's tracks in order
+    Album,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum Repeat {
+    #[default]
+    None,
+    One,
+    All,
+}
+
+impl Repeat {
+    fn cycle(self) -> Self {
+        match self {
+            Repeat::None => Repeat::All,
+            Repeat::All => Repeat::One,
+            Repeat::One => Repeat::None,
+        }
+    }
+}
+
+enum Target {
+    At(usize),
+    /// Start the following pass over the whole queue
+    Wrap,
+    End,
+}
+
+/// Every track given on the command line or read from playlists, and the position within them
+pub struct Queue {
+    tracks: Vec<Track>,
+    /// Play order of the current pass, as indexes into `tracks`
+    order: Vec<usize>,
+    position: usize,
+    shuffle: Option<Shuffle>,
+    /// The kind of shuffle turned on by [`Queue::toggle_shuffle`]
+    shuffle_kind: Shuffle,
+    repeat: Repeat,
+    /// Play order of the pass after this one while shuffling
+    next_pass: Vec<usize>,
+    /// Album of every track, read on the first album shuffle
+    albums: Option<Vec<String>>,
+}
+
+impl Queue {
+    pub fn new(tracks: Vec<Track>, shuffle: Option<Shuffle>, repeat: Repeat) -> Self {
+        let mut queue = Queue {
+            order: (0..tracks.len()).collect(),
+            tracks,
+            position: 0,
+            shuffle: None,
+            shuffle_kind: shuffle.unwrap_or(Shuffle::Track),
+            repeat,
+            next_pass: Vec::new(),
+            albums: None,
+        };
+
+        if shuffle.is_some() {
+            queue.order.clear();
+            queue.toggle_shuffle();
+        }
+        queue
+    }
+
+    pub fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// The track to play now, or `None` once the end of the queue has been passed
+    pub fn current(&self) -> Option<&Track> {
+        self.order.get(self.position).map(|&i| &self.tracks[i])
+    }
+
+    /// The track that applying `command` would move to
+    pub fn peek(&self, command: Command) -> Option<&Track> {
+        let index = match self.target(command) {
+            Target::At(position) => self.order.get(position),
+            Target::Wrap if self.shuffle.is_some() => self.next_pass.first(),
+            Target::Wrap => self.order.first(),
+            Target::End => None,
+        };
+        index.map(|&i| &self.tracks[i])
+    }
+
+    pub fn apply(&mut self, command: Command) {
+        match self.target(command) {
+            Target::At(position) => self.position = position,
+            Target::Wrap => {
+                if self.shuffle.is_some() {
+                    self.order = std::mem::take(&mut self.next_pass);
+                    self.next_pass = self.next_pass_after(self.order.last().copied());
+                }
+                self.position = 0;
+            }
+            Target::End => self.position = self.order.len(),
+        }
+    }
+
+    fn target(&self, command: Command) -> Target {
+        let last = self.order.len().saturating_sub(1);
+        match command {
+            Command::Ended if self.repeat == Repeat::One => Target::At(self.position),
+            Command::Ended | Command::Next if self.position < last => Target::At(self.position + 1),
+            Command::Ended | Command::Next if self.repeat == Repeat::All => Target::Wrap,
+            Command::Ended | Command::Next | Command::Quit => Target::End,
+            Command::Previous => Target::At(self.position.saturating_sub(1)),
+            Command::Restart => Target::At(self.position),
+            Command::Jump(index) => Target::At(
+                self.order
+                    .iter()
+                    .position(|&i| i == index)
+                    .unwrap_or(self.position),
+            ),
+        }
+    }
+
+    pub fn cycle_repeat(&mut self) -> Repeat {
+        self.repeat = self.repeat.cycle();
+        self.repeat
+    }
+
+    /// Turns shuffle on or off without interrupting the current track.
+    /// Turning it on only reorders the tracks that have not been played in this pass yet.
+    pub fn toggle_shuffle(&mut self) -> Option<Shuffle> {
+        let current = self.order.get(self.position).copied();
+
+        if self.shuffle.take().is_some() {
+            self.order = (0..self.tracks.len()).collect();
+            self.position = current.unwrap_or(self.tracks.len());
+            self.next_pass.clear();
+            return None;
+        }
+
+        self.shuffle = Some(self.shuffle_kind);
+
+        let played = self.order.len().min(self.position + 1);
+        self.order.truncate(played);
+        let unplayed = (0..self.tracks.len())
+            .filter(|i| !self.order.contains(i))
+            .collect();
+        let unplayed = self.shuffled(unplayed);
+        self.order.extend(unplayed);
+
+        self.next_pass = self.next_pass_after(self.order.last().copied());
+        self.shuffle
+    }
+
+    /// A fresh shuffle of the whole queue that does not start with the track just played
+    fn next_pass_after(&mut self, last_played: Option<usize>) -> Vec<usize> {
+        let mut next = self.shuffled((0..self.tracks.len()).collect());
+        if self.shuffle == Some(Shuffle::Track)
+            && next.len() > 1
+            && next.first() == last_played.as_ref()
+        {
+            let last = next.len() - 1;
+            next.swap(0, last);
+        }
+        next
+    }
+
+    fn shuffled(&mut self, mut indexes: Vec<usize>) -> Vec<usize> {
+        let mut rng = rand::rng();
+
+        if self.shuffle_kind == Shuffle::Track {
+            indexes.shuffle(&mut rng);
+            return indexes;
+        }
+
+        let albums = self.albums();
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for i in indexes {
+            match groups.iter_mut().find(|g| albums[g[0]] == albums[i]) {
+                Some(group) => group.push(i),
+                None => groups.push(vec![i]),
+            }
+        }
+        groups.shuffle(&mut rng);
+        groups.concat()
+    }
+
+    fn albums(&mut self) -> &[String] {
+        let tracks = &self.tracks;
+        self.albums
+            .get_or_insert_with(|| tracks.iter().map(album_of).collect())
+    }
+}
+
+/// Groups a track by its album tag, falling back to the directory it lives in
+fn album_of(track: &Track) -> String {
+    if track.is_remote() {
+        return track.location.clone();
+    }
+
+    let path = Path::new(&track.location);
+    let album = Probe::open(path)
+        .and_then(|probe| probe.read())
+        .ok()
+        .and_then(|file| {
+            file.primary_tag()
+                .and_then(|tag| tag.album().map(|album| album.to_string()))
+        });
+
+    album.or_else(|| track.album.clone()).unwrap_or_else(|| {
+        path.parent()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracks(count: usize) -> Vec<Track> {
+        (0..count)
+            .map(|i| Track::new(format!("/music/{}.flac", i), None))
+            .collect()
+    }
+
+    /// Index of `track` within the queue as it was given
+    fn index(track: &Track) -> Option<usize> {
+        let name = Path::new(&track.location).file_stem()?.to_str()?;
+        name.parse().ok()
+    }
+
+    fn current(queue: &Queue) -> Option<usize> {
+        queue.current().and_then(index)
+    }
+
+    fn peek(queue: &Queue, command: Command) -> Option<usize> {
+        queue.peek(command).and_then(index)
+    }
+
+    /// The tracks of one pass in the order played, from the current one on
+    fn pass(queue: &mut Queue) -> Vec<usize> {
+        let mut played = vec![current(queue).unwrap()];
+        for _ in 1..queue.len() {
+            queue.apply(Command::Ended);
+            played.push(current(queue).unwrap());
+        }
+        played
+    }
+
+    #[test]
+    fn shuffle_plays_every_track_once_per_pass() {
+        let mut queue = Queue::new(tracks(12), Some(Shuffle::Track), Repeat::All);
+        let mut last = None;
+        for _ in 0..5 {
+            let mut played = pass(&mut queue);
+            // A new pass never starts with the track that ended the last one
+            assert_ne!(played.first().copied(), last);
+            last = played.last().copied();
+            played.sort();
+            assert_eq!(played, (0..12).collect::<Vec<_>>());
+            queue.apply(Command::Ended);
+        }
+    }
+
+    #[test]
+    fn album_shuffle_keeps_album_order() {
+        // Three albums, given interleaved
+        let tracks = tracks(12)
+            .into_iter()
+            .enumerate()
+            .map(|(i, track)| Track {
+                album: Some(format!("Album {}", i % 3)),
+                ..track
+            })
+            .collect();
+        let mut queue = Queue::new(tracks, Some(Shuffle::Album), Repeat::All);
+        for _ in 0..3 {
+            let played = pass(&mut queue);
+            for album in played.chunks(4) {
+                let first = album[0];
+                assert_eq!(album, [first, first + 3, first + 6, first + 9]);
+            }
+            queue.apply(Command::Ended);
+        }
+    }
+
+    #[test]
+    fn turning_shuffle_on_keeps_what_was_played() {
+        let mut queue = Queue::new(tracks(8), None, Repeat::None);
+        queue.apply(Command::Next);
+        queue.apply(Command::Next);
+        assert_eq!(queue.toggle_shuffle(), Some(Shuffle::Track));
+        assert_eq!(current(&queue), Some(2));
+
+        let mut rest = vec![2];
+        for _ in 0..5 {
+            queue.apply(Command::Next);
+            rest.push(current(&queue).unwrap());
+        }
+        rest.sort();
+        assert_eq!(rest, [2, 3, 4, 5, 6, 7]);
+
+        // Back in the given order, around whichever track was left playing
+        assert_eq!(queue.toggle_shuffle(), None);
+        let now = current(&queue).unwrap();
+        queue.apply(Command::Previous);
+        assert_eq!(current(&queue), Some(now - 1));
+    }
+
+    #[test]
+    fn moves_stay_in_bounds() {
+        let mut queue = Queue::new(tracks(3), None, Repeat::None);
+        queue.apply(Command::Previous);
+        assert_eq!(current(&queue), Some(0));
+        queue.apply(Command::Restart);
+        assert_eq!(current(&queue), Some(0));
+
+        queue.apply(Command::Jump(2));
+        assert_eq!(current(&queue), Some(2));
+        queue.apply(Command::Jump(3));
+        assert_eq!(current(&queue), Some(2));
+        queue.apply(Command::Restart);
+        assert_eq!(current(&queue), Some(2));
+        queue.apply(Command::Previous);
+        assert_eq!(current(&queue), Some(1));
+    }
+
+    #[test]
+    fn jump_finds_the_track_while_shuffled() {
+        let mut queue = Queue::new(tracks(10), Some(Shuffle::Track), Repeat::None);
+        for index in [7, 0, 9, 3] {
+            assert_eq!(peek(&queue, Command::Jump(index)), Some(index));
+            queue.apply(Command::Jump(index));
+            assert_eq!(current(&queue), Some(index));
+        }
+    }
+
+    #[test]
+    fn repeat_one_stays_on_the_track_until_skipped() {
+        let mut queue = Queue::new(tracks(2), None, Repeat::One);
+        queue.apply(Command::Ended);
+        assert_eq!(current(&queue), Some(0));
+        queue.apply(Command::Next);
+        assert_eq!(current(&queue), Some(1));
+        queue.apply(Command::Ended);
+        assert_eq!(current(&queue), Some(1));
+        // Skipping past the last track ends the queue, as there is no pass to repeat
+        queue.apply(Command::Next);
+        assert_eq!(current(&queue), None);
+    }
+
+    #[test]
+    fn repeat_all_wraps_to_the_start() {
+        let mut queue = Queue::new(tracks(3), None, Repeat::All);
+        queue.apply(Command::Jump(2));
+        assert_eq!(peek(&queue, Command::Ended), Some(0));
+        queue.apply(Command::Ended);
+        assert_eq!(current(&queue), Some(0));
+        queue.apply(Command::Jump(2));
+        queue.apply(Command::Next);
+        assert_eq!(current(&queue), Some(0));
+        // Going back from the first track does not wrap the other way
+        queue.apply(Command::Previous);
+        assert_eq!(current(&queue), Some(0));
+    }
+
+    #[test]
+    fn without_repeat_the_queue_ends() {
+        let mut queue = Queue::new(tracks(2), None, Repeat::None);
+        queue.apply(Command::Ended);
+        assert_eq!(peek(&queue, Command::Ended), None);
+        queue.apply(Command::Ended);
+        assert_eq!(current(&queue), None);
+    }
+}
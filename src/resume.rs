@@ -0,0 +1,264 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Positions this close to the start are not worth coming back to
+const MIN_POSITION: Duration = Duration::from_secs(10);
+/// Files left with less than this to play start over instead
+const NEARLY_FINISHED: Duration = Duration::from_secs(30);
+/// Only the most recently left files are remembered
+const MAX_ENTRIES: usize = 1000;
+
+/// A file as it was when it was left; a changed size or mtime makes the position meaningless
+#[derive(PartialEq)]
+struct Key {
+    path: String,
+    size: u64,
+    mtime: u64,
+}
+
+impl Key {
+    fn of(path: &str) -> Option<Key> {
+        let path = fs::canonicalize(path).ok()?;
+        let metadata = fs::metadata(&path).ok()?;
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Key {
+            path: path.to_string_lossy().to_string(),
+            size: metadata.len(),
+            mtime: mtime.as_secs(),
+        })
+    }
+}
+
+/// Where each file was left off, kept in `minau/positions` under the user's data directory.
+/// Each line holds the position in milliseconds, size, mtime and path, separated by tabs.
+pub struct Positions {
+    file: Option<PathBuf>,
+    /// Most recently left first
+    entries: Vec<(Key, Duration)>,
+}
+
+impl Positions {
+    /// Reads the state file, starting empty if there is none or it cannot be read
+    pub fn load() -> Self {
+        Self::load_from(dirs::data_dir().map(|dir| dir.join("minau").join("positions")))
+    }
+
+    /// Reads positions from `file` and saves them back there, skipping lines it cannot read
+    fn load_from(file: Option<PathBuf>) -> Self {
+        let content = file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .unwrap_or_default();
+
+        let entries = content
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(4, '\t');
+                let millis = fields.next()?.parse().ok()?;
+                let size = fields.next()?.parse().ok()?;
+                let mtime = fields.next()?.parse().ok()?;
+                let path = fields.next()?.to_string();
+                Some((Key { path, size, mtime }, Duration::from_millis(millis)))
+            })
+            .collect();
+
+        Positions { file, entries }
+    }
+
+    /// Where to pick `path` up again, unless it was left near its start or its end
+    pub fn get(&self, path: &str, duration: Duration) -> Option<Duration> {
+        let key = Key::of(path)?;
+        let (_, position) = self.entries.iter().find(|(entry, _)| *entry == key)?;
+        worth_resuming(*position, duration).then_some(*position)
+    }
+
+    /// Records that `path` was left at `position`, or forgets it if there is nothing to resume
+    pub fn remember(
+        &mut self,
+        path: &str,
+        position: Duration,
+        duration: Duration,
+    ) -> io::Result<()> {
+        if !worth_resuming(position, duration) {
+            return self.forget(path);
+        }
+        let Some(key) = Key::of(path) else {
+            return Ok(());
+        };
+
+        self.entries.retain(|(entry, _)| entry.path != key.path);
+        self.entries.insert(0, (key, position));
+        self.entries.truncate(MAX_ENTRIES);
+        self.save()
+    }
+
+    /// Makes `path` start from the beginning next time
+    pub fn forget(&mut self, path: &str) -> io::Result<()> {
+        let Ok(path) = fs::canonicalize(path) else {
+            return Ok(());
+        };
+        let path = path.to_string_lossy();
+
+        let before = self.entries.len();
+        self.entries.retain(|(entry, _)| entry.path != path);
+        if self.entries.len() == before {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let content: String = self
+            .entries
+            .iter()
+            .map(|(key, position)| {
+                format!(
+                    "{}\t{}\t{}\t{}\n",
+                    position.as_millis(),
+                    key.size,
+                    key.mtime,
+                    key.path
+                )
+            })
+            .collect();
+
+        // Written aside and renamed so an interrupted save never loses every position
+        let temp = file.with_extension("tmp");
+        fs::write(&temp, content)?;
+        fs::rename(temp, file)
+    }
+}
+
+fn worth_resuming(position: Duration, duration: Duration) -> bool {
+    position >= MIN_POSITION && position + NEARLY_FINISHED < duration
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::SystemTime;
+
+    /// A directory of its own for each test, emptied first
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("minau-resume-{}", std::process::id()))
+            .join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(path: &PathBuf, content: &str) -> String {
+        fs::write(path, content).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    const TEN_MINUTES: Duration = Duration::from_secs(600);
+
+    #[test]
+    fn key_follows_size_and_mtime() {
+        let dir = scratch("key");
+        let song = write(&dir.join("song.flac"), "audio");
+        let key = Key::of(&song).unwrap();
+
+        write(&dir.join("song.flac"), "longer audio");
+        let resized = Key::of(&song).unwrap();
+        assert!(resized != key);
+        assert_eq!(resized.path, key.path);
+
+        let touched = SystemTime::now() + Duration::from_secs(3600);
+        File::options()
+            .write(true)
+            .open(&song)
+            .unwrap()
+            .set_modified(touched)
+            .unwrap();
+        let retouched = Key::of(&song).unwrap();
+        assert!(retouched != resized);
+        assert_eq!(retouched.size, resized.size);
+
+        assert!(Key::of(&dir.join("missing.flac").to_string_lossy()).is_none());
+    }
+
+    #[test]
+    fn cutoffs_near_start_and_end() {
+        assert!(!worth_resuming(
+            MIN_POSITION - Duration::from_millis(1),
+            TEN_MINUTES
+        ));
+        assert!(worth_resuming(MIN_POSITION, TEN_MINUTES));
+        assert!(worth_resuming(
+            TEN_MINUTES - NEARLY_FINISHED - Duration::from_millis(1),
+            TEN_MINUTES
+        ));
+        assert!(!worth_resuming(TEN_MINUTES - NEARLY_FINISHED, TEN_MINUTES));
+        // Too short to be worth coming back to anywhere
+        assert!(!worth_resuming(
+            MIN_POSITION,
+            MIN_POSITION + NEARLY_FINISHED
+        ));
+    }
+
+    #[test]
+    fn positions_survive_a_reload() {
+        let dir = scratch("reload");
+        let state = dir.join("state").join("positions");
+        let first = write(&dir.join("first.flac"), "one");
+        let second = write(&dir.join("second\tname.flac"), "two");
+
+        let mut positions = Positions::load_from(Some(state.clone()));
+        positions
+            .remember(&first, Duration::from_millis(61_500), TEN_MINUTES)
+            .unwrap();
+        positions
+            .remember(&second, Duration::from_secs(120), TEN_MINUTES)
+            .unwrap();
+        // Left near its end, so forgotten again
+        positions
+            .remember(&first, Duration::from_secs(590), TEN_MINUTES)
+            .unwrap();
+        positions
+            .remember(&first, Duration::from_millis(61_500), TEN_MINUTES)
+            .unwrap();
+
+        let mut content = fs::read_to_string(&state).unwrap();
+        content.push_str("not a position\n12\tbig\t3\t/elsewhere.flac\n\n");
+        fs::write(&state, content).unwrap();
+
+        let positions = Positions::load_from(Some(state));
+        assert_eq!(positions.entries.len(), 2);
+        assert_eq!(
+            positions.get(&first, TEN_MINUTES),
+            Some(Duration::from_millis(61_500))
+        );
+        assert_eq!(
+            positions.get(&second, TEN_MINUTES),
+            Some(Duration::from_secs(120))
+        );
+        // Not worth resuming within a shorter file
+        assert_eq!(positions.get(&second, Duration::from_secs(140)), None);
+    }
+
+    #[test]
+    fn changed_files_start_over() {
+        let dir = scratch("changed");
+        let song = write(&dir.join("song.flac"), "audio");
+        let mut positions = Positions::load_from(Some(dir.join("positions")));
+        positions
+            .remember(&song, Duration::from_secs(60), TEN_MINUTES)
+            .unwrap();
+
+        write(&dir.join("song.flac"), "re-encoded audio");
+        assert_eq!(positions.get(&song, TEN_MINUTES), None);
+    }
+}
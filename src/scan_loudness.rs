@@ -0,0 +1,89 @@
+use crate::directory::{self, SortOrder};
+use crate::warn;
+use minau::loudness::{self, Loudness, Meter};
+use std::path::{Path, PathBuf};
+
+/// Measures every file, and every directory's files together as an album.
+/// Returns how many files could not be measured or tagged.
+pub fn scan_loudness(paths: &[String], write_tags: bool, hidden: bool) -> usize {
+    let mut albums: Vec<(PathBuf, Vec<String>)> = Vec::new();
+    for path in paths {
+        let files = if Path::new(path).is_dir() {
+            directory::expand(path, SortOrder::Natural, hidden)
+                .into_iter()
+                .map(|track| track.location)
+                .collect()
+        } else {
+            vec![path.clone()]
+        };
+
+        for file in files {
+            let dir = Path::new(&file)
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            match albums.iter_mut().find(|(album, _)| *album == dir) {
+                Some((_, files)) => files.push(file),
+                None => albums.push((dir, vec![file])),
+            }
+        }
+    }
+
+    let mut failed = 0;
+    for (dir, files) in albums {
+        let mut measured: Vec<(String, Meter)> = Vec::new();
+        for file in files {
+            match loudness::scan(&file) {
+                Ok(meter) => {
+                    println!("{}  {}", format_loudness(&meter.loudness()), file);
+                    measured.push((file, meter));
+                }
+                Err(e) => {
+                    warn!("Skipping {}: {}", file, e);
+                    failed += 1;
+                }
+            }
+        }
+
+        if measured.is_empty() {
+            continue;
+        }
+
+        let (files, meters): (Vec<String>, Vec<Meter>) = measured.into_iter().unzip();
+        let album = Meter::combined(&meters);
+        println!("{}  [album] {}", format_loudness(&album), dir.display());
+
+        if write_tags {
+            for (file, meter) in files.iter().zip(&meters) {
+                let loudness = meter.loudness();
+                // Silence has no gain to give, so its tags are left as they were
+                if loudness.gain().is_none() {
+                    continue;
+                }
+                if let Err(e) = loudness::write_replay_gain(file, &loudness, Some(&album)) {
+                    warn!("Failed to tag {}: {}", file, e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    failed
+}
+
+fn format_loudness(loudness: &Loudness) -> String {
+    let (integrated, gain) = match (loudness.integrated, loudness.gain()) {
+        (Some(lufs), Some(gain)) => (format!("{:6.2} LUFS", lufs), format!("{:+6.2} dB", gain)),
+        _ => (format!("{:>11}", "silent"), format!("{:>9}", "-")),
+    };
+
+    let peak = match loudness.true_peak_db() {
+        Some(db) => format!("{:6.2} dBTP", db),
+        None => format!("{:>11}", "-"),
+    };
+
+    format!(
+        "{}  LRA {:5.2} LU  {}  gain {}",
+        integrated, loudness.range, peak, gain
+    )
+}
@@ -0,0 +1,120 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// When playback should stop by itself
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Sleep {
+    After(Duration),
+    /// Once the track playing at that moment ends
+    EndOfTrack,
+}
+
+/// How quickly the volume comes back when a fading timer is turned off
+const WAKE_RAMP: Duration = Duration::from_millis(500);
+
+/// What the `z` key cycles through after "off"
+const PRESETS: [Sleep; 5] = [
+    Sleep::After(Duration::from_secs(15 * 60)),
+    Sleep::After(Duration::from_secs(30 * 60)),
+    Sleep::After(Duration::from_secs(60 * 60)),
+    Sleep::After(Duration::from_secs(90 * 60)),
+    Sleep::EndOfTrack,
+];
+
+/// Reads `track` or a duration such as `45m` or `1h 30m`
+impl FromStr for Sleep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "track" {
+            return Ok(Sleep::EndOfTrack);
+        }
+        humantime::parse_duration(s)
+            .map(Sleep::After)
+            .map_err(|e| format!("expected a duration or \"track\": {}", e))
+    }
+}
+
+pub struct SleepTimer {
+    sleep: Option<Sleep>,
+    /// When an [`Sleep::After`] timer runs out
+    deadline: Option<Instant>,
+    /// How long before the deadline the volume starts going down
+    fade: Duration,
+    /// Whether the volume has been sent down, whichever player it was sent to
+    dozing: bool,
+}
+
+impl SleepTimer {
+    pub fn new(sleep: Option<Sleep>, fade: Duration) -> Self {
+        let mut timer = SleepTimer {
+            sleep: None,
+            deadline: None,
+            fade,
+            dozing: false,
+        };
+        timer.set(sleep);
+        timer
+    }
+
+    fn set(&mut self, sleep: Option<Sleep>) {
+        self.sleep = sleep;
+        self.deadline = match sleep {
+            Some(Sleep::After(after)) => Some(Instant::now() + after),
+            _ => None,
+        };
+    }
+
+    /// Switches to the next preset, restarting the countdown, and describes it
+    pub fn cycle(&mut self) -> String {
+        let next = match self.sleep {
+            None => Some(PRESETS[0]),
+            Some(sleep) => PRESETS
+                .iter()
+                .position(|&preset| preset == sleep)
+                .map_or(Some(PRESETS[0]), |i| PRESETS.get(i + 1).copied()),
+        };
+        self.set(next);
+        self.describe()
+    }
+
+    pub fn describe(&self) -> String {
+        match self.sleep {
+            None => String::from("off"),
+            Some(Sleep::After(after)) => humantime::format_duration(after).to_string(),
+            Some(Sleep::EndOfTrack) => String::from("end of track"),
+        }
+    }
+
+    /// Time left on an [`Sleep::After`] timer
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Fades through `ramp_volume` as the deadline nears, and brings the volume back if the
+    /// timer is turned off or pushed back meanwhile. The fade is asked for again on every
+    /// tick, aiming at the same deadline, so a player that starts partway through follows
+    /// it as well as one carried over from the last track. Returns true once playback
+    /// should stop.
+    pub fn tick(&mut self, ramp_volume: impl Fn(f32, Duration)) -> bool {
+        let fading = self.remaining().filter(|&left| left <= self.fade);
+        match fading {
+            Some(left) if left.is_zero() => return true,
+            Some(left) => {
+                ramp_volume(0.0, left);
+                self.dozing = true;
+            }
+            None if self.dozing => {
+                ramp_volume(1.0, WAKE_RAMP);
+                self.dozing = false;
+            }
+            None => {}
+        }
+        false
+    }
+
+    pub fn ends_with_track(&self) -> bool {
+        self.sleep == Some(Sleep::EndOfTrack)
+    }
+}
@@ -0,0 +1,59 @@
+use std::path::Path;
+use std::time::Duration;
+use url::Url;
+
+/// A single entry to play, either a local file or a remote stream
+#[derive(Clone, PartialEq)]
+pub struct Track {
+    pub location: String,
+    pub title: Option<String>,
+    /// Artist named by the playlist, shown when the file's tags name none
+    pub artist: Option<String>,
+    /// Album named by the playlist, shown when the file's tags name none
+    pub album: Option<String>,
+    /// Length named by the playlist, shown for streams that do not tell their own
+    pub duration: Option<Duration>,
+}
+
+impl Track {
+    pub fn new<S: Into<String>>(location: S, title: Option<String>) -> Self {
+        let location = location.into();
+
+        let location = match Url::parse(&location).map(|url| url.to_file_path()) {
+            Ok(Ok(file_path)) => file_path.to_string_lossy().to_string(),
+            _ => location,
+        };
+
+        Self {
+            location,
+            title,
+            artist: None,
+            album: None,
+            duration: None,
+        }
+    }
+
+    pub fn is_remote(&self) -> bool {
+        Url::parse(&self.location).is_ok()
+    }
+
+    /// Title to show for a stream, led by the artist when the playlist names one
+    pub fn display_title(&self) -> Option<String> {
+        let title = self.title.clone()?;
+        Some(match &self.artist {
+            Some(artist) => format!("{} - {}", artist, title),
+            None => title,
+        })
+    }
+
+    /// Lowercased extension of the file, or of the path of a URL, leaving its query out
+    pub fn extension(&self) -> Option<String> {
+        let path = match Url::parse(&self.location) {
+            Ok(url) => url.path().to_string(),
+            Err(_) => self.location.clone(),
+        };
+        Path::new(&path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+    }
+}
@@ -0,0 +1,186 @@
+use crate::error::Result;
+use crate::m3u;
+use crate::track::Track;
+use std::time::Duration;
+use std::{fs, io, path::Path};
+use url::Url;
+use xml::reader::{EventReader, XmlEvent};
+
+/// One `<track>` of an XSPF playlist, as written in the file
+#[derive(Default)]
+pub struct XspfEntry {
+    /// The first `<location>`, a URI that may be relative to the playlist
+    pub location: String,
+    pub title: Option<String>,
+    pub creator: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+/// Parses XSPF text, reading the `<track>`s of its `<trackList>`. Tracks without a location
+/// are left out, and locations are returned as written.
+pub fn parse(xspf: &str) -> Result<Vec<XspfEntry>> {
+    let mut entries = Vec::new();
+    // Names of the elements the parser is inside, outermost first
+    let mut open: Vec<String> = Vec::new();
+    let mut entry = XspfEntry::default();
+    let mut text = String::new();
+
+    for event in EventReader::from_str(xspf) {
+        match event? {
+            XmlEvent::StartElement { name, .. } => {
+                open.push(name.local_name);
+                text.clear();
+            }
+            XmlEvent::Characters(chars) | XmlEvent::CData(chars) => text.push_str(&chars),
+            XmlEvent::EndElement { name } => {
+                open.pop();
+                let value = text.trim();
+                let value = (!value.is_empty()).then(|| value.to_string());
+                text.clear();
+
+                // Extensions may nest elements of the same names deeper inside a track
+                if inside(&open, &["trackList", "track"]) {
+                    match name.local_name.as_str() {
+                        "location" if entry.location.is_empty() => {
+                            entry.location = value.unwrap_or_default();
+                        }
+                        "title" => entry.title = value,
+                        "creator" => entry.creator = value,
+                        "album" => entry.album = value,
+                        // Milliseconds
+                        "duration" => {
+                            entry.duration = value
+                                .and_then(|ms| ms.parse().ok())
+                                .map(Duration::from_millis);
+                        }
+                        _ => {}
+                    }
+                } else if name.local_name == "track" && inside(&open, &["trackList"]) {
+                    let done = std::mem::take(&mut entry);
+                    if !done.location.is_empty() {
+                        entries.push(done);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+/// Whether the innermost open elements are `names`
+fn inside(open: &[String], names: &[&str]) -> bool {
+    open.len() >= names.len()
+        && open[open.len() - names.len()..]
+            .iter()
+            .zip(names)
+            .all(|(open, name)| open == name)
+}
+
+/// Turns entries into tracks, resolving their locations against `base`, the location of
+/// the playlist itself
+fn tracks(entries: Vec<XspfEntry>, base: &Url) -> Vec<Track> {
+    entries
+        .into_iter()
+        .filter_map(|entry| {
+            let location = base.join(&entry.location).ok()?;
+            Some(Track {
+                artist: entry.creator,
+                album: entry.album,
+                duration: entry.duration,
+                ..Track::new(location.as_str(), entry.title)
+            })
+        })
+        .collect()
+}
+
+/// Reads an XSPF playlist, resolving relative locations against the playlist's directory
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Track>> {
+    let path = fs::canonicalize(path)?;
+    let content = fs::read_to_string(&path)?;
+    let base = Url::from_file_path(&path).map_err(|()| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Playlist path can not be turned into a URL",
+        )
+    })?;
+    Ok(tracks(parse(&content)?, &base))
+}
+
+/// Downloads an XSPF playlist, resolving relative locations against the URL it finally
+/// came from
+pub async fn load_url(url: &str) -> Result<Vec<Track>> {
+    let (content, base) = m3u::download(url).await?;
+    Ok(tracks(parse(&content)?, &base))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYLIST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <title>Mix</title>
+  <location>http://example.com/mix.xspf</location>
+  <trackList>
+    <track>
+      <location>music/one.flac</location>
+      <location>music/one.mp3</location>
+      <title>One</title>
+      <creator>Artist</creator>
+      <album>Album</album>
+      <duration>185500</duration>
+      <extension application="http://example.com/app">
+        <title>Not the title</title>
+        <location>not/the/location.ogg</location>
+        <duration>1</duration>
+        <meta><creator>Nobody</creator></meta>
+      </extension>
+    </track>
+    <track>
+      <title>Nowhere to be found</title>
+      <duration>60000</duration>
+    </track>
+    <track>
+      <location>https://radio.example.com/stream</location>
+    </track>
+  </trackList>
+</playlist>
+"#;
+
+    #[test]
+    fn nested_extensions_are_left_out() {
+        let entries = parse(PLAYLIST).unwrap();
+        let one = &entries[0];
+        assert_eq!(one.location, "music/one.flac");
+        assert_eq!(one.title.as_deref(), Some("One"));
+        assert_eq!(one.creator.as_deref(), Some("Artist"));
+        assert_eq!(one.album.as_deref(), Some("Album"));
+        assert_eq!(one.duration, Some(Duration::from_millis(185_500)));
+    }
+
+    #[test]
+    fn tracks_without_location_are_left_out() {
+        let entries = parse(PLAYLIST).unwrap();
+        let locations: Vec<_> = entries.iter().map(|e| e.location.as_str()).collect();
+        assert_eq!(
+            locations,
+            ["music/one.flac", "https://radio.example.com/stream"]
+        );
+        assert_eq!(entries[1].title, None);
+        assert_eq!(entries[1].duration, None);
+    }
+
+    #[test]
+    fn tracks_carry_the_duration() {
+        let base = Url::parse("https://example.com/lists/mix.xspf").unwrap();
+        let tracks = tracks(parse(PLAYLIST).unwrap(), &base);
+        assert_eq!(
+            tracks[0].location,
+            "https://example.com/lists/music/one.flac"
+        );
+        assert_eq!(tracks[0].duration, Some(Duration::from_millis(185_500)));
+        assert_eq!(tracks[1].duration, None);
+    }
+}